@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use vanguard_rs::manifest;
+
+// Any input must either parse or return an error, never panic.
+fuzz_target!(|data: &[u8]| {
+	if let Ok(contents) = std::str::from_utf8(data) {
+		let _ = manifest::deserialize_manifest(contents);
+	}
+});
@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use vanguard_rs::manifest;
+use vanguard_rs::manifest::manifest_spec::vg_1_1;
+
+// Any manifest which parses must survive re-serialization as vg-1.1 unchanged, apart from its version.
+fuzz_target!(|data: &[u8]| {
+	if let Ok(contents) = std::str::from_utf8(data) {
+		if let Ok(mut parsed) = manifest::deserialize_manifest(contents) {
+			parsed.version = "vg-1.1".to_owned();
+			let serialized = match vg_1_1::serialize_manifest(&parsed) {
+				Ok(serialized) => serialized,
+				// Values outside of what TOML can represent (ie, sizes above i64::MAX) are rejected, not corrupted.
+				Err(_) => return,
+			};
+			assert_eq!(manifest::deserialize_manifest(&serialized).unwrap(), parsed);
+		}
+	}
+});
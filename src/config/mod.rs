@@ -11,6 +11,8 @@ use std::path;
 pub const CFG_VERSION: &str = "1.0";
 /// Config file name
 pub const CFG_FILE_NAME: &str = "vanguard.toml";
+/// Default content store directory name, relative to the config file.
+pub const DEFAULT_STORAGE_DIR: &str = "store";
 
 /// Gets current application config
 pub fn get_config() -> Config {
@@ -42,8 +44,14 @@ pub fn write_config_file(config: &Config) -> Result<(), ConfigError> {
 }
 
 /// Gets the current application directory as a PathBuf.
+/// Vanguard's persisted state is stored in this directory, next to `vanguard.toml`.
+pub fn get_app_dir() -> Result<path::PathBuf, ConfigError> {
+    Ok(std::env::current_dir()?)
+}
+
+/// Gets the path of the config file as a PathBuf.
 fn get_cfg_file_path() -> Result<path::PathBuf, ConfigError> {
-    let mut cfg_file_path = std::env::current_dir()?;
+    let mut cfg_file_path = get_app_dir()?;
     cfg_file_path.push(CFG_FILE_NAME);
     Ok(cfg_file_path)
 }
@@ -53,14 +61,35 @@ fn get_cfg_file_path() -> Result<path::PathBuf, ConfigError> {
 #[serde(default)]
 pub struct Config {
     /// Version identifier for config file
-    version: String,
+    pub version: String,
     /// Maximum parallel file workers to use.
-    maximum_parallel_files: u8,
-    /// If true,
-    use_symlinked_storage: bool,
+    pub maximum_parallel_files: u8,
+    /// If true, downloaded files are kept once in a shared content store and linked into application paths.
+    pub use_symlinked_storage: bool,
+    /// Path of the shared content store. Defaults to `store` in the application directory if missing.
+    pub storage_path: Option<String>,
+    /// Path of a local mirror directory (ie, a USB stick or network share), laid out like the download server, with
+    /// each file at its URL path. Files found there are used before downloading. Unused if missing.
+    pub local_mirror: Option<String>,
+    /// Maximum combined download rate of every manifest, in bytes per second. Unlimited if missing.
+    pub max_download_rate: Option<u64>,
+    /// Paths of PEM files of additional certificate authorities to trust, alongside the bundled web roots (ie, a
+    /// corporate proxy's CA, or the private CA of self-hosted mirrors).
+    pub ca_bundles: Vec<String>,
+    /// Array-table of daily time windows during which download rate limits are lifted (ie, overnight).
+    #[serde(rename = "unthrottled_window", skip_serializing_if = "Vec::is_empty")]
+    pub unthrottled_windows: Vec<TimeWindow>,
+    /// Retry policy for manifest and file requests.
+    pub retry: RetryPolicy,
+    /// Proxies for manifest and file requests.
+    pub proxy: ProxyConfig,
+    /// Array-table of certificates pinned per host. Requests to a host with pins must be served with a pinned
+    /// certificate, in addition to a valid certificate chain.
+    #[serde(rename = "certificate_pin", skip_serializing_if = "Vec::is_empty")]
+    pub certificate_pins: Vec<CertificatePin>,
     /// Array-table of manifests in use
-    #[serde(rename = "manifest")]
-    manifests: Vec<ManifestConfig>,
+    #[serde(rename = "manifest", skip_serializing_if = "Vec::is_empty")]
+    pub manifests: Vec<ManifestConfig>,
 }
 impl Default for Config {
     fn default() -> Config {
@@ -68,24 +97,147 @@ impl Default for Config {
             version: CFG_VERSION.to_owned(),
             maximum_parallel_files: 4,
             use_symlinked_storage: true,
+            storage_path: None,
+            local_mirror: None,
+            max_download_rate: None,
+            ca_bundles: Vec::new(),
+            unthrottled_windows: Vec::new(),
+            retry: Default::default(),
+            proxy: Default::default(),
+            certificate_pins: Vec::new(),
             manifests: Vec::new()
         }
     }
 }
 
+impl Config {
+    /// Gets the path of the shared content store.
+    pub fn get_storage_path(&self) -> Result<path::PathBuf, ConfigError> {
+        match self.storage_path {
+            Some(ref storage_path) => Ok(path::PathBuf::from(storage_path)),
+            None => Ok(get_app_dir()?.join(DEFAULT_STORAGE_DIR)),
+        }
+    }
+}
+
+/// Retry policy for failed requests. Retries back off exponentially from `backoff_base_ms`, up to `backoff_cap_ms`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Maximum attempts of a request, including the first. 1 disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry, in milliseconds. Doubles with each further retry.
+    pub backoff_base_ms: u64,
+    /// Maximum backoff delay before a retry, in milliseconds.
+    pub backoff_cap_ms: u64,
+    /// Maximum delay before a retry requested by a server with `Retry-After`, in milliseconds.
+    pub max_retry_after_ms: u64,
+    /// If true, backoff delays are randomised to between half and all of their length, so clients which failed
+    /// together do not retry together.
+    pub jitter: bool,
+    /// HTTP status codes which are retried.
+    pub retry_statuses: Vec<u16>,
+    /// If true, connection failures, timeouts and interrupted transfers are retried.
+    pub retry_network_errors: bool,
+}
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff_base_ms: 500,
+            backoff_cap_ms: 30_000,
+            max_retry_after_ms: 600_000,
+            jitter: true,
+            retry_statuses: vec![408, 429, 500, 502, 503, 504],
+            retry_network_errors: true,
+        }
+    }
+}
+
+/// Proxy settings. Each request uses the proxy for its URL's scheme, unless its host is excluded with `no_proxy`.
+/// Settings which are missing fall back to the standard environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// Proxy for http requests, as an `http://` or `socks5://` URL, which may include credentials.
+    /// Falls back to `HTTP_PROXY`, then `ALL_PROXY`.
+    pub http: Option<String>,
+    /// Proxy for https requests, as an `http://` or `socks5://` URL, which may include credentials.
+    /// Falls back to `HTTPS_PROXY`, then `ALL_PROXY`.
+    pub https: Option<String>,
+    /// Hosts which are connected to directly. Entries match the host and its subdomains, and `*` matches every host.
+    /// Falls back to the comma separated list in `NO_PROXY`.
+    pub no_proxy: Option<Vec<String>>,
+}
+
+/// Certificates pinned for a host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CertificatePin {
+    /// Host name the pins apply to (ie, cdn.example.com).
+    pub host: String,
+    /// SHA-256 fingerprints of the pinned certificates, as hex. Any certificate in the chain served by the host may
+    /// be pinned, so a CA's certificate may be pinned to allow its certificates to be renewed.
+    pub sha256: Vec<String>,
+}
+
+/// Daily time window, in local time. Windows which end before they start span midnight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    /// Start of the window, as `HH:MM`.
+    pub start: String,
+    /// End of the window, as `HH:MM`. The window ends at the start of this minute.
+    pub end: String,
+}
+
+impl TimeWindow {
+    /// Returns true if the window contains `minute`, a number of minutes since midnight.
+    pub fn contains(&self, minute: u32) -> Result<bool, ConfigError> {
+        let start = parse_time(&self.start)?;
+        let end = parse_time(&self.end)?;
+        Ok(match start <= end {
+            true => start <= minute && minute < end,
+            false => minute >= start || minute < end,
+        })
+    }
+}
+
+/// Parses a `HH:MM` time of day as a number of minutes since midnight.
+fn parse_time(time: &str) -> Result<u32, ConfigError> {
+    let invalid = || ConfigError::InvalidConfig(format!("Invalid time of day: {}", time));
+    let mut parts = time.trim().splitn(2, ':');
+    let hours: u32 = parts.next().and_then(|h| h.parse().ok()).ok_or_else(invalid)?;
+    let minutes: u32 = parts.next().and_then(|m| m.parse().ok()).ok_or_else(invalid)?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
 /// Per-manifest config data model
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ManifestConfig {
-    /// URL of the manifest. Config entires without a manifest URL will be ignored.
-    url: String,
+    /// URL of the manifest, or of a manifest index listing release channels.
+    /// Config entires without a manifest URL will be ignored.
+    pub url: String,
+    /// Release channel to patch from (ie, stable, beta). Only used if `url` points to a manifest index.
+    /// The index's default channel is used if missing.
+    pub channel: Option<String>,
     /// If true, allows patching from non-https mirrors.
-    allow_insecure_patching: bool,
+    pub allow_insecure_patching: bool,
     /// Path to which application files are downloaded.
-    application_path: String,
+    pub application_path: String,
     /// If true, checksumming is not performed on files.
-    ignore_checksum: bool,
+    pub ignore_checksum: bool,
+    /// If true, manifests describing an older application release than the installed one may be applied.
+    #[serde(default)]
+    pub allow_downgrade: bool,
     /// Vec of launcher profiles to hide, by name.
-    ignore_profiles: Vec<String>,
+    pub ignore_profiles: Vec<String>,
+    /// Maximum download rate of this manifest, in bytes per second. Unlimited if missing.
+    pub max_download_rate: Option<u64>,
+    /// SHA-256 fingerprints of certificates pinned for the host of `url`, as in `CertificatePin.sha256`.
+    #[serde(default)]
+    pub pinned_certificates: Vec<String>,
 }
 
 /// Wrapper for config-related errors.
@@ -131,3 +283,33 @@ impl From<toml::de::Error> for ConfigError {
         ConfigError::InvalidSyntax(item)
     }
 }
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_fixtures;
+
+    #[test]
+    fn should_round_trip_config() {
+        let mut config = Config::default();
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&serialized).unwrap().retry, config.retry);
+
+        config.unthrottled_windows.push(TimeWindow { start: "01:00".to_owned(), end: "07:00".to_owned() });
+        config.manifests.push(ManifestConfig {
+            max_download_rate: Some(1024),
+            ..test_fixtures::manifest_config("https://cdn.example.com/Manifest.toml", path::Path::new("app"))
+        });
+        config.proxy.no_proxy = Some(vec!["localhost".to_owned()]);
+        let pin = CertificatePin { host: "cdn.example.com".to_owned(), sha256: vec!["ab".repeat(32)] };
+        config.certificate_pins.push(pin);
+        let parsed: Config = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(parsed.unthrottled_windows, config.unthrottled_windows);
+        assert_eq!(parsed.manifests[0].max_download_rate, Some(1024));
+        assert_eq!(parsed.proxy, config.proxy);
+        assert_eq!(parsed.certificate_pins, config.certificate_pins);
+    }
+}
@@ -0,0 +1,198 @@
+// --- Imports
+use crate::session::SessionState;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// --- Consts
+/// Minimum time between progress events, so consumers are not flooded by fast downloads.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Defines the stages of a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Phase {
+	FetchingManifest,
+	/// Checking which files need downloading, and whether there is space for them.
+	Preparing,
+	Downloading,
+	/// Swapping downloaded files into the application path.
+	Installing,
+	Complete,
+}
+
+/// Snapshot of the overall progress of a set of downloads.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+	/// Bytes of every file downloaded so far, including bytes resumed from earlier sessions.
+	pub bytes: u64,
+	/// Total bytes to download, if the size of every file is known.
+	pub total_bytes: Option<u64>,
+	/// Average transfer rate since downloading started, in bytes per second.
+	pub bytes_per_second: f64,
+	/// Estimated time until every file is downloaded, if it can be estimated.
+	pub eta: Option<Duration>,
+}
+
+/// Defines an event emitted during a patch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum PatchEvent {
+	PhaseChanged(Phase),
+	/// A manifest was fetched. Contains the configured manifest URL, the manifest's label and its number of files.
+	ManifestFetched(String, String, usize),
+	/// A file is waiting to be downloaded. Contains the file path and its expected size.
+	FileQueued(String, Option<u64>),
+	/// A file started downloading from a mirror. Contains the file path and the mirror URL.
+	FileStarted(String, String),
+	/// Contains the file path, bytes downloaded and its expected size.
+	FileProgress(String, u64, Option<u64>),
+	/// A file was downloaded and verified. Contains the file path and its size.
+	FileVerified(String, u64),
+	/// A file failed to download or install. Contains the file path and a description of the error.
+	FileFailed(String, String),
+	Progress(Progress),
+	/// A request failed and will be retried. Contains the URL, the number of the attempt which failed, the delay
+	/// before retrying and a description of the error.
+	Retrying(String, u32, Duration, String),
+	/// The patch session was paused, resumed or cancelled.
+	SessionChanged(SessionState),
+}
+
+/// Callback which receives patch events.
+pub type EventCallback = dyn Fn(&PatchEvent) + Send + Sync;
+
+/// Receiver of patch events. Sinks are cheap to clone, and clones deliver to the same subscriber.
+/// Events are delivered from whichever thread emits them, including download workers.
+#[derive(Clone, Default)]
+pub struct EventSink {
+	callback: Option<Arc<EventCallback>>,
+}
+
+impl EventSink {
+	/// Creates a sink which calls `callback` with every event.
+	/// Callbacks are called from download workers, so should return quickly.
+	pub fn new<F>(callback: F) -> EventSink
+	where
+		F: Fn(&PatchEvent) + Send + Sync + 'static,
+	{
+		EventSink { callback: Some(Arc::new(callback)) }
+	}
+
+	/// Creates a sink which discards every event.
+	pub fn none() -> EventSink {
+		Default::default()
+	}
+
+	/// Creates a sink which sends every event to the returned channel.
+	/// Events are discarded once the receiver is dropped.
+	pub fn channel() -> (EventSink, mpsc::Receiver<PatchEvent>) {
+		let (sender, receiver) = mpsc::channel();
+		let sender = Mutex::new(sender);
+		let sink = EventSink::new(move |event| {
+			let _ = sender.lock().unwrap().send(event.clone());
+		});
+		(sink, receiver)
+	}
+
+	/// Delivers `event` to the subscriber, if there is one.
+	pub fn emit(&self, event: PatchEvent) {
+		if let Some(ref callback) = self.callback {
+			callback(&event);
+		}
+	}
+}
+
+impl fmt::Debug for EventSink {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("EventSink").field("subscribed", &self.callback.is_some()).finish()
+	}
+}
+
+/// Tracks the overall progress of a set of concurrent downloads.
+/// Progress is kept per file, so bytes of a file which is restarted on another mirror are not counted twice.
+#[derive(Debug)]
+pub struct TransferProgress {
+	sizes: Vec<Option<u64>>,
+	files: Vec<AtomicU64>,
+	transferred: AtomicU64,
+	started: Instant,
+	last_report: Mutex<Instant>,
+}
+
+impl TransferProgress {
+	/// Creates a tracker for files of the given expected sizes.
+	pub fn new(sizes: Vec<Option<u64>>) -> TransferProgress {
+		let now = Instant::now();
+		TransferProgress {
+			files: sizes.iter().map(|_| AtomicU64::new(0)).collect(),
+			sizes,
+			transferred: AtomicU64::new(0),
+			started: now,
+			last_report: Mutex::new(now),
+		}
+	}
+
+	/// Records the bytes downloaded of the file at `position`, of which `transferred` were newly received.
+	pub fn update(&self, position: usize, bytes: u64, transferred: u64) {
+		if let Some(file) = self.files.get(position) {
+			file.store(bytes, Ordering::Relaxed);
+		}
+		self.transferred.fetch_add(transferred, Ordering::Relaxed);
+	}
+
+	/// Returns a snapshot of the progress if none has been reported in the last `PROGRESS_INTERVAL`.
+	pub fn report(&self) -> Option<Progress> {
+		let mut last_report = self.last_report.lock().unwrap();
+		if last_report.elapsed() < PROGRESS_INTERVAL {
+			return None;
+		}
+		*last_report = Instant::now();
+		Some(self.snapshot())
+	}
+
+	/// Gets a snapshot of the progress.
+	pub fn snapshot(&self) -> Progress {
+		let bytes: u64 = self.files.iter().map(|file| file.load(Ordering::Relaxed)).sum();
+		let total_bytes: Option<u64> = self.sizes.iter().copied().sum();
+		let seconds = self.started.elapsed().as_secs_f64();
+		let bytes_per_second = match seconds > 0.0 {
+			true => self.transferred.load(Ordering::Relaxed) as f64 / seconds,
+			false => 0.0,
+		};
+		let eta = match total_bytes {
+			Some(total) if bytes_per_second > 0.0 => {
+				Some(Duration::from_secs_f64(total.saturating_sub(bytes) as f64 / bytes_per_second))
+			}
+			_ => None,
+		};
+		Progress { bytes, total_bytes, bytes_per_second, eta }
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_track_progress() {
+		let progress = TransferProgress::new(vec![Some(100), Some(300)]);
+		progress.update(0, 50, 50);
+		// A file restarted on another mirror replaces its earlier progress.
+		progress.update(1, 100, 100);
+		progress.update(1, 20, 20);
+
+		let snapshot = progress.snapshot();
+		assert_eq!(snapshot.bytes, 70);
+		assert_eq!(snapshot.total_bytes, Some(400));
+		assert!(snapshot.eta.is_some());
+		assert_eq!(TransferProgress::new(vec![Some(100), None]).snapshot().total_bytes, None);
+
+		let (sink, receiver) = EventSink::channel();
+		sink.emit(PatchEvent::PhaseChanged(Phase::Downloading));
+		assert_eq!(receiver.try_recv().unwrap(), PatchEvent::PhaseChanged(Phase::Downloading));
+	}
+}
@@ -0,0 +1,367 @@
+// --- Imports
+use super::download::{DownloadResult, PARTIAL_EXTENSION};
+use super::hash::{self, FileHash, HashAlgorithm, StreamHasher};
+use super::install::{self, get_temp_dir, resolve_path};
+use super::mirror::MirrorTracker;
+use super::release::{self, InstalledRelease};
+use super::store::ContentStore;
+use super::FileManagerError;
+use crate::config::{Config, ManifestConfig};
+use crate::manifest::manifest_spec::{vg_1_1, Manifest, ManifestFile};
+use crate::manifest::{self, diff};
+use crate::session::PatchSession;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+// --- Consts
+/// Name of the bundle entry holding the manifest the bundle installs.
+const MANIFEST_ENTRY: &str = "manifest.toml";
+/// Name of the bundle entry holding the base manifest of an incremental bundle.
+const BASE_ENTRY: &str = "base.toml";
+/// Directory of bundle entries holding file contents, as `blobs/<algorithm>/<digest>`.
+const BLOBS_DIR: &str = "blobs";
+/// Name of the directory bundles are unpacked to, within the temporary download directory.
+const BUNDLE_DIR_NAME: &str = "bundle";
+/// Size of the buffer used to unpack bundle entries.
+const UNPACK_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Defines the outcome of importing a bundle.
+#[derive(Debug)]
+pub struct BundleImport {
+	/// The manifest installed by the bundle.
+	pub manifest: Manifest,
+	/// Paths of files removed since the base manifest of an incremental bundle, which should be removed from disk.
+	pub removed: Vec<String>,
+	/// One result per file installed from the bundle, in manifest order.
+	pub results: Vec<DownloadResult>,
+}
+
+/// Exports `manifest` and the contents of its files to a single tar archive at `dest`, so it can be installed on a
+/// machine without network access with `import_bundle`.
+/// If `since` is provided, the bundle is incremental, holding only files added or changed since that manifest, and
+/// can only be imported over an install of it.
+/// Contents are taken from the content store when it holds them, or else from the installed files of
+/// `manifest_config`, which are verified first so a damaged install is never exported. Files with identical contents
+/// are bundled once, and every file must have a hash so it can be verified on import.
+/// The archive is written to a temporary file first, so `dest` only exists once the bundle is complete.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest, whose application path holds its installed files.
+/// * `manifest` - The manifest to export.
+/// * `since` - The manifest an incremental bundle is based on, if any.
+/// * `dest` - Path of the bundle to write.
+pub fn export_bundle(
+	config: &Config, manifest_config: &ManifestConfig, manifest: &Manifest, since: Option<&Manifest>, dest: &Path,
+) -> Result<(), FileManagerError> {
+	let application_path = Path::new(&manifest_config.application_path);
+	let store = match config.use_symlinked_storage {
+		true => Some(ContentStore::from_config(config)?),
+		false => None,
+	};
+	let mut partial = dest.as_os_str().to_owned();
+	partial.push(".");
+	partial.push(PARTIAL_EXTENSION);
+	let partial = PathBuf::from(partial);
+	match write_bundle(application_path, store.as_ref(), manifest, since, &partial) {
+		Ok(()) => Ok(fs::rename(&partial, dest)?),
+		Err(e) => {
+			let _ = fs::remove_file(&partial);
+			Err(e)
+		}
+	}
+}
+
+/// Installs the bundle at `bundle`, created by `export_bundle`, into the application path of `manifest_config`
+/// without network access.
+/// The bundle is unpacked first, verifying every file's contents against its hash, and rejected before anything is
+/// installed if any file is damaged or missing. Files are then installed as by `install::install_files`, so the
+/// install is all or nothing and may be rolled back.
+/// Incremental bundles only install the files added or changed since their base manifest, which the application path
+/// is expected to hold. Paths removed since are returned, for the caller to remove.
+/// Bundles older than the installed release are refused, unless the manifest config allows downgrades.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest to install.
+/// * `bundle` - Path of the bundle.
+/// * `mirrors` - Mirror tracker of the install. Bundled files are local, so are not ranked.
+/// * `session` - Session the install belongs to.
+pub fn import_bundle(
+	config: &Config, manifest_config: &ManifestConfig, bundle: &Path, mirrors: &Arc<MirrorTracker>,
+	session: &PatchSession,
+) -> Result<BundleImport, FileManagerError> {
+	let application_path = Path::new(&manifest_config.application_path);
+	let store = match config.use_symlinked_storage {
+		true => Some(ContentStore::from_config(config)?),
+		false => None,
+	};
+	let dir = get_temp_dir(application_path, store.as_ref()).join(BUNDLE_DIR_NAME);
+	let result = unpack_bundle(bundle, &dir).and_then(|(manifest, base, blobs)| {
+		release::check_downgrade(application_path, &manifest, manifest_config.allow_downgrade)?;
+		let (files, removed) = bundled_files(&manifest, base.as_ref());
+		// File URLs must be absolute.
+		let root = fs::canonicalize(&dir)?;
+		let mut sources = Vec::with_capacity(files.len());
+		for file in files {
+			let hash = hash::strongest_hash(&file).ok_or_else(|| FileManagerError::UnverifiableFile(file.path.clone()))?;
+			if !blobs.contains(&hash) {
+				return Err(FileManagerError::InvalidBundle(format!("Missing contents of {}", file.path)));
+			}
+			let url = Url::from_file_path(root.join(blob_file_name(&hash)))
+				.map_err(|_| FileManagerError::InvalidBundle(format!("Could not locate contents of {}", file.path)))?;
+			sources.push(ManifestFile { url: vec![url.to_string()], ..file });
+		}
+		let results = install::install_files(config, manifest_config, &sources, mirrors, session)?;
+		if results.iter().all(|download| download.result.is_ok()) {
+			let installed = InstalledRelease::new(&manifest.label, manifest.app_version.as_deref(), manifest.build);
+			if let Err(e) = installed.save(application_path) {
+				log::warn!("Could not record the installed release of {} - {}", manifest_config.application_path, e);
+			}
+		}
+		Ok(BundleImport { manifest, removed, results })
+	});
+	let _ = fs::remove_dir_all(&dir);
+	result
+}
+
+/// Gets the files of `manifest` held in a bundle based on `since`, in manifest order, and the paths removed since it.
+fn bundled_files(manifest: &Manifest, since: Option<&Manifest>) -> (Vec<ManifestFile>, Vec<String>) {
+	match since {
+		Some(since) => {
+			let diff = diff::diff_manifests(since, manifest);
+			let changed: HashSet<&str> = diff.files_to_fetch().map(|file| file.path.as_str()).collect();
+			let files = manifest.files.iter().filter(|file| changed.contains(file.path.as_str())).cloned().collect();
+			(files, diff.removed)
+		}
+		None => (manifest.files.clone(), Vec::new()),
+	}
+}
+
+/// Gets the name of the bundle entry holding contents matching `hash`.
+fn blob_entry(hash: &FileHash) -> String {
+	format!("{}/{}/{}", BLOBS_DIR, hash.algorithm(), hash.value())
+}
+
+/// Gets the hash of the contents held by the bundle entry `name`, if it is a blob entry.
+fn parse_blob_entry(name: &str) -> Option<FileHash> {
+	let parts: Vec<&str> = name.split('/').collect();
+	let algorithm = match parts[..] {
+		[BLOBS_DIR, "md5", _] => HashAlgorithm::Md5,
+		[BLOBS_DIR, "sha1", _] => HashAlgorithm::Sha1,
+		[BLOBS_DIR, "sha256", _] => HashAlgorithm::Sha256,
+		_ => return None,
+	};
+	FileHash::new(algorithm, parts[2])
+}
+
+/// Gets the file name of unpacked contents matching `hash`.
+fn blob_file_name(hash: &FileHash) -> String {
+	format!("{}-{}", hash.algorithm(), hash.value())
+}
+
+/// Writes a bundle of `manifest` to `path`.
+fn write_bundle(
+	application_path: &Path, store: Option<&ContentStore>, manifest: &Manifest, since: Option<&Manifest>, path: &Path,
+) -> Result<(), FileManagerError> {
+	let mut builder = tar::Builder::new(BufWriter::new(fs::File::create(path)?));
+	append_manifest(&mut builder, MANIFEST_ENTRY, manifest)?;
+	if let Some(since) = since {
+		append_manifest(&mut builder, BASE_ENTRY, since)?;
+	}
+	let (files, _) = bundled_files(manifest, since);
+	let mut bundled = HashSet::new();
+	for file in &files {
+		let hash = hash::strongest_hash(file).ok_or_else(|| FileManagerError::UnverifiableFile(file.path.clone()))?;
+		if bundled.insert(hash.clone()) {
+			let source = find_source(application_path, store, file, &hash)?;
+			builder.append_path_with_name(&source, blob_entry(&hash))?;
+		}
+	}
+	builder.into_inner()?.flush()?;
+	Ok(())
+}
+
+/// Adds `manifest` to a bundle as the entry `name`, in the latest manifest format.
+fn append_manifest<W: Write>(
+	builder: &mut tar::Builder<W>, name: &str, manifest: &Manifest,
+) -> Result<(), FileManagerError> {
+	let document = vg_1_1::serialize_manifest(manifest)?;
+	let mut header = tar::Header::new_gnu();
+	header.set_size(document.len() as u64);
+	header.set_mode(0o644);
+	header.set_cksum();
+	builder.append_data(&mut header, name, document.as_bytes())?;
+	Ok(())
+}
+
+/// Finds a copy of `file` with contents matching `hash`, to be bundled.
+/// Stored blobs were verified when they were stored, so only installed files are verified.
+fn find_source(
+	application_path: &Path, store: Option<&ContentStore>, file: &ManifestFile, hash: &FileHash,
+) -> Result<PathBuf, FileManagerError> {
+	if let Some(store) = store {
+		if store.contains(hash) {
+			return Ok(store.blob_path(hash));
+		}
+	}
+	let installed = resolve_path(application_path, &file.path)?;
+	let actual = hash::hash_file(&installed, hash.algorithm())?;
+	match actual == *hash {
+		true => Ok(installed),
+		false => Err(FileManagerError::HashMismatch(file.path.clone(), hash.clone(), actual)),
+	}
+}
+
+/// Unpacks the bundle at `bundle` to `dir`, verifying the contents of every blob as it is unpacked.
+/// Returns the bundle's manifest, its base manifest if it is incremental, and the hashes of the unpacked blobs.
+fn unpack_bundle(bundle: &Path, dir: &Path) -> Result<(Manifest, Option<Manifest>, HashSet<FileHash>), FileManagerError> {
+	let _ = fs::remove_dir_all(dir);
+	fs::create_dir_all(dir)?;
+	let mut archive = tar::Archive::new(BufReader::new(fs::File::open(bundle)?));
+	let (mut manifest, mut base, mut blobs) = (None, None, HashSet::new());
+	let mut buffer = vec![0u8; UNPACK_BUFFER_SIZE];
+	for entry in archive.entries()? {
+		let mut entry = entry?;
+		let name = entry.path()?.to_string_lossy().into_owned();
+		match name.as_str() {
+			MANIFEST_ENTRY => manifest = Some(read_manifest(&mut entry)?),
+			BASE_ENTRY => base = Some(read_manifest(&mut entry)?),
+			_ => {
+				let hash =
+					parse_blob_entry(&name).ok_or_else(|| FileManagerError::InvalidBundle(format!("Unexpected entry {}", name)))?;
+				let mut writer = BufWriter::new(fs::File::create(dir.join(blob_file_name(&hash)))?);
+				let mut hasher = StreamHasher::new(hash.algorithm());
+				loop {
+					let read = entry.read(&mut buffer)?;
+					if read == 0 {
+						break;
+					}
+					writer.write_all(&buffer[..read])?;
+					hasher.update(&buffer[..read]);
+				}
+				writer.flush()?;
+				let actual = hasher.finish();
+				if actual != hash {
+					return Err(FileManagerError::HashMismatch(name, hash, actual));
+				}
+				blobs.insert(hash);
+			}
+		}
+	}
+	let manifest = manifest.ok_or_else(|| FileManagerError::InvalidBundle(format!("Missing {}", MANIFEST_ENTRY)))?;
+	Ok((manifest, base, blobs))
+}
+
+/// Reads a manifest held in a bundle entry.
+fn read_manifest<R: Read>(entry: &mut R) -> Result<Manifest, FileManagerError> {
+	let mut document = String::new();
+	entry.read_to_string(&mut document)?;
+	Ok(manifest::deserialize_manifest(&document)?)
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::test_fixtures::{self, manifest};
+
+	fn manifest_config(application_path: &Path) -> ManifestConfig {
+		test_fixtures::manifest_config("https://cdn.example.com/Manifest.toml", application_path)
+	}
+
+	/// Installs `contents` at `path` of `app`, returning its manifest entry.
+	fn install(app: &Path, path: &str, contents: &[u8]) -> ManifestFile {
+		fs::create_dir_all(app.join(path).parent().unwrap()).unwrap();
+		fs::write(app.join(path), contents).unwrap();
+		test_fixtures::file(path, &format!("https://cdn.example.com/{}", path), contents)
+	}
+
+	fn entries(bundle: &Path) -> Vec<String> {
+		let mut archive = tar::Archive::new(fs::File::open(bundle).unwrap());
+		archive.entries().unwrap().map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned()).collect()
+	}
+
+	#[test]
+	fn should_export_and_import_bundles() {
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config { use_symlinked_storage: false, ..Default::default() };
+		let (source, target) = (dir.path().join("source"), dir.path().join("target"));
+		let mirrors = Arc::new(MirrorTracker::new());
+		let session = PatchSession::default();
+
+		let v1 = manifest(vec![
+			install(&source, "app.exe", b"app v1"),
+			install(&source, "data/a.pak", b"shared"),
+			install(&source, "data/b.pak", b"shared"),
+			install(&source, "old.dll", b"old"),
+		]);
+		let bundle = dir.path().join("v1.tar");
+		export_bundle(&config, &manifest_config(&source), &v1, None, &bundle).unwrap();
+		// Identical contents are bundled once.
+		assert_eq!(entries(&bundle).len(), 4);
+
+		let imported = import_bundle(&config, &manifest_config(&target), &bundle, &mirrors, &session).unwrap();
+		assert_eq!(imported.manifest, v1);
+		assert!(imported.removed.is_empty());
+		assert!(imported.results.iter().all(|r| r.result.is_ok()));
+		assert_eq!(fs::read(target.join("app.exe")).unwrap(), b"app v1");
+		assert_eq!(fs::read(target.join("data/b.pak")).unwrap(), b"shared");
+
+		// Incremental bundles hold only the changes since their base manifest.
+		let v2 = manifest(vec![
+			install(&source, "app.exe", b"app v2"),
+			v1.files[1].clone(),
+			v1.files[2].clone(),
+			install(&source, "new.dll", b"new"),
+		]);
+		let bundle = dir.path().join("v2.tar");
+		export_bundle(&config, &manifest_config(&source), &v2, Some(&v1), &bundle).unwrap();
+		assert_eq!(entries(&bundle).len(), 4);
+
+		let imported = import_bundle(&config, &manifest_config(&target), &bundle, &mirrors, &session).unwrap();
+		assert_eq!(imported.removed, vec!["old.dll"]);
+		assert_eq!(imported.results.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(), vec!["app.exe", "new.dll"]);
+		assert_eq!(fs::read(target.join("app.exe")).unwrap(), b"app v2");
+		assert_eq!(fs::read(target.join("new.dll")).unwrap(), b"new");
+
+		// Damaged installs are not exported.
+		fs::write(source.join("new.dll"), b"bad").unwrap();
+		match export_bundle(&config, &manifest_config(&source), &v2, Some(&v1), &bundle) {
+			Err(FileManagerError::HashMismatch(ref path, _, _)) => assert_eq!(path, "new.dll"),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn should_reject_damaged_bundles() {
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config { use_symlinked_storage: false, ..Default::default() };
+		let source = dir.path().join("source");
+		let v1 = manifest(vec![install(&source, "app.exe", b"app v1")]);
+		let hash = hash::strongest_hash(&v1.files[0]).unwrap();
+
+		let bundle = dir.path().join("damaged.tar");
+		let mut builder = tar::Builder::new(fs::File::create(&bundle).unwrap());
+		append_manifest(&mut builder, MANIFEST_ENTRY, &v1).unwrap();
+		let mut header = tar::Header::new_gnu();
+		header.set_size(6);
+		header.set_cksum();
+		builder.append_data(&mut header, blob_entry(&hash), &b"app v2"[..]).unwrap();
+		builder.finish().unwrap();
+
+		let target = dir.path().join("target");
+		let mirrors = Arc::new(MirrorTracker::new());
+		match import_bundle(&config, &manifest_config(&target), &bundle, &mirrors, &PatchSession::default()) {
+			Err(FileManagerError::HashMismatch(_, _, _)) => (),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		assert!(!target.join("app.exe").exists());
+		assert_eq!(parse_blob_entry(&blob_entry(&hash)), Some(hash));
+		assert_eq!(parse_blob_entry("blobs/sha256/../../app.exe"), None);
+	}
+}
@@ -0,0 +1,126 @@
+// --- Imports
+use super::download::DownloadedFile;
+use super::hash::FileHash;
+use super::index::{FileIndex, IndexEntry};
+use super::install::resolve_path;
+use super::store::ContentStore;
+use super::FileManagerError;
+use crate::manifest::manifest_spec::ManifestFile;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Installed files of an application path, by hash, used to stage files without downloading them.
+/// Only files recorded in the file index are known, and a file is only used if it is unchanged since it was indexed.
+#[derive(Debug, Default)]
+pub struct LocalFiles {
+	files: HashMap<FileHash, Vec<(PathBuf, IndexEntry)>>,
+}
+
+impl LocalFiles {
+	/// Collects the indexed files of an application path.
+	pub fn new(application_path: &Path, index: &FileIndex) -> LocalFiles {
+		let mut files: HashMap<FileHash, Vec<(PathBuf, IndexEntry)>> = HashMap::new();
+		for (path, entry) in &index.entries {
+			if let Ok(location) = resolve_path(application_path, path) {
+				files.entry(entry.hash.clone()).or_default().push((location, entry.clone()));
+			}
+		}
+		LocalFiles { files }
+	}
+
+	/// Gets the location and size of an installed file with contents matching `hash`, if there is one.
+	pub fn find(&self, hash: &FileHash) -> Option<(&Path, u64)> {
+		self.files.get(hash)?.iter().find_map(|(location, entry)| match fs::metadata(location) {
+			Ok(ref metadata) if metadata.is_file() && entry.matches(metadata) => Some((location.as_path(), entry.size)),
+			_ => None,
+		})
+	}
+}
+
+/// Copies the installed file at `source` to `temp_path`, staging it as a download of `file`.
+/// The installed file is left in place, as the install may still need it.
+/// # Arguments
+/// * `source` - The installed file, with contents matching `hash`.
+/// * `file` - The manifest file to stage.
+/// * `hash` - Hash of the file's contents.
+/// * `temp_path` - Staging location of the file.
+pub fn stage_local(
+	source: &Path, file: &ManifestFile, hash: &FileHash, temp_path: &Path,
+) -> Result<DownloadedFile, FileManagerError> {
+	if let Some(parent) = temp_path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	let size = fs::copy(source, temp_path)?;
+	Ok(DownloadedFile {
+		path: file.path.clone(),
+		temp_path: temp_path.to_path_buf(),
+		size,
+		hash: hash.clone(),
+		url: Url::from_file_path(source).map(|u| u.to_string()).unwrap_or_default(),
+	})
+}
+
+/// Places another copy of a file at `dest`, after the first copy has been placed at `placed`.
+/// If a content store is provided, `dest` is linked to the stored blob instead.
+/// # Arguments
+/// * `hash` - Hash of the file's contents.
+/// * `placed` - Location of the placed copy.
+/// * `dest` - Destination path.
+/// * `store` - The content store, if symlinked storage is enabled.
+pub fn place_duplicate(
+	hash: &FileHash, placed: &Path, dest: &Path, store: Option<&ContentStore>,
+) -> Result<(), FileManagerError> {
+	if let Some(store) = store {
+		store.materialize(hash, dest)?;
+		return Ok(());
+	}
+	if let Some(parent) = dest.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	if fs::symlink_metadata(dest).is_ok() {
+		fs::remove_file(dest)?;
+	}
+	fs::copy(placed, dest)?;
+	Ok(())
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::file_manager::hash::HashAlgorithm;
+
+	#[test]
+	fn should_find_unchanged_local_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let app = dir.path();
+		fs::create_dir_all(app.join("redist")).unwrap();
+		fs::write(app.join("redist/vcredist.exe"), b"abc").unwrap();
+		let hash = FileHash::new(HashAlgorithm::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+		let mut index = FileIndex::default();
+		index.record("redist/vcredist.exe", &app.join("redist/vcredist.exe"), hash.clone()).unwrap();
+
+		let local = LocalFiles::new(app, &index);
+		assert_eq!(local.find(&hash), Some((app.join("redist/vcredist.exe").as_path(), 3)));
+
+		let file = ManifestFile {
+			path: "profile/vcredist.exe".to_owned(),
+			url: Vec::new(),
+			size: Some(3),
+			md5: None,
+			sha1: Some(hash.value().to_owned()),
+			sha256: None,
+		};
+		let staged = stage_local(&app.join("redist/vcredist.exe"), &file, &hash, &app.join("tmp/x.part")).unwrap();
+		assert_eq!(fs::read(&staged.temp_path).unwrap(), b"abc");
+		assert!(app.join("redist/vcredist.exe").exists());
+
+		// Changed files are not used.
+		fs::write(app.join("redist/vcredist.exe"), b"abcd").unwrap();
+		assert_eq!(local.find(&hash), None);
+	}
+}
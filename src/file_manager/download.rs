@@ -0,0 +1,845 @@
+// --- Imports
+use super::hash::{self, FileHash, HashAlgorithm, StreamHasher};
+use super::mirror::MirrorTracker;
+use super::partial::PartialState;
+use super::pool;
+use super::throttle::Throttle;
+use super::FileManagerError;
+use crate::config::{Config, RetryPolicy};
+use crate::events::{PatchEvent, TransferProgress, PROGRESS_INTERVAL};
+use crate::manifest::manifest_spec::ManifestFile;
+use crate::net::client::Client;
+use crate::net::{self, retry, NetError};
+use crate::session::{PatchSession, SessionState};
+use fs2::FileExt;
+use percent_encoding::percent_decode_str;
+use std::fs;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use url::Url;
+
+// --- Consts
+/// Connection timeout for file downloads, in milliseconds.
+const CONNECT_TIMEOUT_MS: u64 = 15_000;
+/// Read timeout for file downloads, in milliseconds. Applies to each read, not the whole transfer.
+const READ_TIMEOUT_MS: u64 = 30_000;
+/// Size of the buffer used to stream response bodies to disk.
+const DOWNLOAD_BUFFER_SIZE: usize = 64 * 1024;
+/// Extension of in-progress download files.
+pub const PARTIAL_EXTENSION: &str = "part";
+/// Number of bytes downloaded between saves of a partial download's state.
+const STATE_SAVE_INTERVAL: u64 = 8 * 1024 * 1024;
+
+/// Defines a file to be downloaded.
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+	/// The file to download.
+	pub file: ManifestFile,
+	/// If false, the downloaded file is hashed but not compared against the manifest.
+	pub verify_checksum: bool,
+	/// If false, only https mirrors are used.
+	pub allow_insecure: bool,
+}
+
+impl DownloadJob {
+	/// Gets the mirrors of the file permitted by the secure patching policy.
+	pub fn allowed_urls(&self) -> Vec<String> {
+		self.file.url.iter().filter(|url| net::check_url(url, self.allow_insecure).is_ok()).cloned().collect()
+	}
+}
+
+/// Defines a downloaded file, held in a temporary location until it is installed.
+#[derive(Debug, Clone)]
+pub struct DownloadedFile {
+	/// Manifest path of the file, relative to the application path.
+	pub path: String,
+	/// Location of the downloaded file.
+	pub temp_path: PathBuf,
+	/// Size of the file in bytes.
+	pub size: u64,
+	/// Hash of the downloaded contents, using the file's strongest manifest hash algorithm, or sha256 if the
+	/// manifest provides no hash.
+	pub hash: FileHash,
+	/// URL of the mirror which served the file.
+	pub url: String,
+}
+
+/// Defines the outcome of a single file download.
+#[derive(Debug)]
+pub struct DownloadResult {
+	/// Manifest path of the file.
+	pub path: String,
+	pub result: Result<DownloadedFile, FileManagerError>,
+}
+
+/// Worker pool downloader. Files are downloaded concurrently, up to `Config.maximum_parallel_files` at a time,
+/// streamed to temporary files and hashed as they are written.
+/// Each file's mirrors are tried in order of preference until one serves a verified copy. Local sources, from the
+/// local mirror directory or `file://` URLs, are tried before any remote mirror.
+pub struct Downloader {
+	client: Client,
+	parallel_files: usize,
+	temp_dir: PathBuf,
+	mirrors: Arc<MirrorTracker>,
+	throttle: Throttle,
+	session: PatchSession,
+	retry: RetryPolicy,
+	local_mirror: Option<PathBuf>,
+}
+
+impl Downloader {
+	/// Creates a downloader which writes in-progress files to `temp_dir`.
+	/// # Arguments
+	/// * `config` - Application config.
+	/// * `temp_dir` - Directory for in-progress downloads. Created if missing.
+	pub fn new(config: &Config, temp_dir: &Path) -> Result<Downloader, FileManagerError> {
+		Downloader::with_mirrors(config, temp_dir, Arc::new(MirrorTracker::new()))
+	}
+
+	/// Creates a downloader which ranks mirrors with `mirrors`. Trackers may be shared between downloaders.
+	/// # Arguments
+	/// * `config` - Application config.
+	/// * `temp_dir` - Directory for in-progress downloads. Created if missing.
+	/// * `mirrors` - Mirror tracker, usually holding previously recorded statistics.
+	pub fn with_mirrors(
+		config: &Config, temp_dir: &Path, mirrors: Arc<MirrorTracker>,
+	) -> Result<Downloader, FileManagerError> {
+		fs::create_dir_all(temp_dir)?;
+		Ok(Downloader {
+			client: Client::new(config)?,
+			parallel_files: (config.maximum_parallel_files as usize).max(1),
+			temp_dir: temp_dir.to_path_buf(),
+			mirrors,
+			throttle: Throttle::unlimited(),
+			session: PatchSession::default(),
+			retry: config.retry.clone(),
+			local_mirror: config.local_mirror.as_ref().map(|dir| fs::canonicalize(dir).unwrap_or_else(|_| dir.into())),
+		})
+	}
+
+	/// Sets the session downloads belong to, which receives their events and may pause or cancel them.
+	/// Downloads use a running session which discards events by default.
+	pub fn set_session(&mut self, session: PatchSession) {
+		self.session = session;
+	}
+
+	/// Sets the client used to send requests. Downloads use a client with the network settings of the downloader's
+	/// config by default.
+	pub fn set_client(&mut self, client: Client) {
+		self.client = client;
+	}
+
+	/// Sets the rate limits applied to downloads. Downloads are unlimited by default.
+	/// Limits are shared by every worker of the downloader, and by any other downloader using the same limiters.
+	pub fn set_throttle(&mut self, throttle: Throttle) {
+		self.throttle = throttle;
+	}
+
+	/// Gets the mirror tracker of this downloader.
+	pub fn mirrors(&self) -> &MirrorTracker {
+		&self.mirrors
+	}
+
+	/// Probes the latency of unmeasured mirrors of `jobs`, so they can be ranked before downloading. Mirrors are probed
+	/// up to `Config.maximum_parallel_files` at a time.
+	/// Jobs with a single permitted URL have nothing to rank, so their mirrors are not probed.
+	pub fn probe_mirrors(&self, jobs: &[DownloadJob]) {
+		let remote = |job: &DownloadJob| -> Vec<String> {
+			job.allowed_urls().into_iter().filter(|url| !net::is_local(url)).collect()
+		};
+		let urls: Vec<String> = jobs.iter().map(remote).filter(|urls| urls.len() > 1).flatten().collect();
+		self.mirrors.probe(&self.client, &urls.iter().collect::<Vec<_>>(), self.parallel_files);
+	}
+
+	/// Downloads every job, returning one result per job in the order given.
+	/// A failed file does not stop other downloads. Progress of the whole set of jobs is reported to the downloader's
+	/// event sink.
+	pub fn download(&self, jobs: Vec<DownloadJob>) -> Vec<DownloadResult> {
+		Downloader::download_shared(vec![(self, jobs)]).pop().unwrap_or_default()
+	}
+
+	/// Downloads the jobs of several downloaders on one pool of workers, returning the results of each downloader's
+	/// jobs in the order given. Each job is downloaded by its own downloader, with its client, rate limits and
+	/// temporary directory, and the pool is bounded by the lowest `Config.maximum_parallel_files` of the downloaders.
+	/// Progress of the whole set of jobs is reported to the event sink of the first downloader.
+	pub fn download_shared(batches: Vec<(&Downloader, Vec<DownloadJob>)>) -> Vec<Vec<DownloadResult>> {
+		let sizes: Vec<Option<u64>> =
+			batches.iter().flat_map(|(_, jobs)| jobs.iter().map(|job| job.file.size)).collect();
+		let mut queued = Vec::with_capacity(sizes.len());
+		let progress = TransferProgress::new(sizes);
+		let workers = batches.iter().map(|(downloader, _)| downloader.parallel_files).min().unwrap_or(1);
+		let session = batches.first().map(|(downloader, _)| downloader.session.clone());
+		let mut batch_sizes = Vec::with_capacity(batches.len());
+		for (downloader, jobs) in batches {
+			batch_sizes.push(jobs.len());
+			for job in jobs {
+				downloader.session.emit(PatchEvent::FileQueued(job.file.path.clone(), job.file.size));
+				queued.push((downloader, queued.len(), job));
+			}
+		}
+		let results = pool::parallel_map(queued, workers, |(downloader, position, job)| {
+			let result = downloader.download_tracked(&job, position, &progress);
+			DownloadResult { path: job.file.path, result }
+		});
+		if let Some(session) = session {
+			session.emit(PatchEvent::Progress(progress.snapshot()));
+		}
+		let mut results = results.into_iter();
+		batch_sizes.into_iter().map(|size| results.by_ref().take(size).collect()).collect()
+	}
+
+	/// Downloads a single file to a temporary location and verifies it.
+	/// Mirrors are failed over on connection errors, HTTP errors, timeouts and size or hash mismatches. Local disk
+	/// errors are returned immediately, as another mirror would not fix them.
+	/// Interrupted downloads are kept and resumed by the next attempt, from any mirror, if the file has a known hash.
+	/// Mirrors not permitted by the job's secure patching policy are never contacted.
+	/// Copies in the local mirror directory and `file://` URLs are tried first, and verified the same way. Local
+	/// sources which are missing or unreadable fail over to the next source.
+	/// Transient errors are retried on the same mirror under `Config.retry` before failing over, resuming from the
+	/// progress already made.
+	/// Downloads stop between reads while the downloader's session is paused or cancelled. Paused downloads continue
+	/// from the same mirror once resumed, and cancelled downloads fail with `Cancelled`, keeping their progress.
+	pub fn download_file(&self, job: &DownloadJob) -> Result<DownloadedFile, FileManagerError> {
+		self.download_tracked(job, 0, &TransferProgress::new(vec![job.file.size]))
+	}
+
+	/// Downloads a single file as the job at `position` of `progress`, reporting its outcome to the event sink.
+	fn download_tracked(
+		&self, job: &DownloadJob, position: usize, progress: &TransferProgress,
+	) -> Result<DownloadedFile, FileManagerError> {
+		let result = self.try_mirrors(job, position, progress);
+		match result {
+			Ok(ref downloaded) => self.session.emit(PatchEvent::FileVerified(downloaded.path.clone(), downloaded.size)),
+			Err(ref e) => self.session.emit(PatchEvent::FileFailed(job.file.path.clone(), e.to_string())),
+		}
+		result
+	}
+
+	/// Downloads a single file from the first of its mirrors to serve a verified copy.
+	fn try_mirrors(
+		&self, job: &DownloadJob, position: usize, progress: &TransferProgress,
+	) -> Result<DownloadedFile, FileManagerError> {
+		let file = &job.file;
+		if file.url.is_empty() {
+			return Err(FileManagerError::NoMirrors(file.path.clone()));
+		}
+		let urls = job.allowed_urls();
+		if urls.is_empty() {
+			return Err(FileManagerError::NoSecureMirrors(file.path.clone()));
+		}
+		let temp_path = self.temp_path(file);
+		let (local, remote): (Vec<String>, Vec<String>) = urls.into_iter().partition(|url| net::is_local(url));
+		let mirrored: Vec<String> = match self.local_mirror {
+			Some(ref dir) => remote.iter().filter_map(|url| local_mirror_url(dir, url)).collect(),
+			None => Vec::new(),
+		};
+
+		let mut failures = Vec::new();
+		for url in mirrored.iter().chain(&local).chain(self.mirrors.order(&remote)) {
+			// Local sources are not ranked, so are left out of mirror statistics.
+			let is_local = net::is_local(url);
+			let mut attempt = 1;
+			let (result, start) = loop {
+				if self.session.wait_while_paused() == SessionState::Cancelled {
+					return Err(FileManagerError::Cancelled);
+				}
+				self.session.emit(PatchEvent::FileStarted(file.path.clone(), url.clone()));
+				let start = Instant::now();
+				let fetched = match is_local {
+					true => self.fetch_local(job, url, &temp_path, position, progress),
+					false => self.fetch(job, url, &temp_path, position, progress),
+				};
+				match fetched {
+					// Stopped by the session, with progress saved.
+					Ok(None) => continue,
+					Ok(Some(fetched)) => break (Ok(fetched), start),
+					Err(FileManagerError::Net(e)) => match retry::retry_delay(&self.retry, attempt, &e) {
+						Some(delay) => {
+							retry::report_retry(&self.retry, self.session.events(), url, attempt, delay, &e);
+							self.session.sleep(delay);
+							attempt += 1;
+						}
+						None => break (Err(e.into()), start),
+					},
+					Err(e) => break (Err(e), start),
+				}
+			};
+			let result = result.and_then(|(size, actual)| {
+				if let Err(e) = verify(job, size, &actual) {
+					// The complete file is bad, so there is nothing worth resuming.
+					PartialState::discard(&temp_path);
+					return Err(e);
+				}
+				Ok(DownloadedFile { path: file.path.clone(), temp_path: temp_path.clone(), size, hash: actual, url: url.clone() })
+			});
+			match result {
+				Ok(downloaded) => {
+					// Throttled transfers measure the rate limit rather than the mirror, so are not sampled.
+					let sampled = if self.throttle.is_limited() { 0 } else { downloaded.size };
+					if !is_local {
+						self.mirrors.record_success(url, sampled, start.elapsed());
+					}
+					return Ok(downloaded);
+				}
+				Err(FileManagerError::FileIO(e)) => return Err(FileManagerError::FileIO(e)),
+				Err(e) => {
+					if !is_local {
+						self.mirrors.record_failure(url);
+					}
+					failures.push((url.clone(), e));
+				}
+			}
+		}
+		Err(FileManagerError::MirrorsExhausted(file.path.clone(), failures))
+	}
+
+	/// Gets the temporary download location of `file`. Names are derived from the manifest path, so the same file
+	/// always downloads to the same location.
+	pub fn temp_path(&self, file: &ManifestFile) -> PathBuf {
+		let mut hasher = StreamHasher::new(HashAlgorithm::Sha1);
+		hasher.update(file.path.as_bytes());
+		self.temp_dir.join(format!("{}.{}", hasher.finish().value(), PARTIAL_EXTENSION))
+	}
+
+	/// Streams `url` to `dest`, returning the number of bytes written and their hash.
+	/// If `dest` holds a resumable partial download, only the remaining bytes are requested. Progress is saved
+	/// periodically and when the transfer fails, so it can be resumed later.
+	/// Returns None if the transfer was stopped as the session is no longer running.
+	/// Files of known size are preallocated, to reduce fragmentation.
+	/// # Arguments
+	/// * `job` - The file being downloaded.
+	/// * `url` - The URL to download.
+	/// * `dest` - Path of the partial download.
+	/// * `position` - Position of the job in `progress`.
+	/// * `progress` - Progress of the set of jobs being downloaded.
+	fn fetch(
+		&self, job: &DownloadJob, url: &str, dest: &Path, position: usize, progress: &TransferProgress,
+	) -> Result<Option<(u64, FileHash)>, FileManagerError> {
+		let allow_insecure = job.allow_insecure;
+		let expected = hash::strongest_hash(&job.file);
+		let expected = expected.as_ref();
+		let algorithm = expected.map(|h| h.algorithm()).unwrap_or(HashAlgorithm::Sha256);
+		let resume = PartialState::load(dest).filter(|state| state.can_resume(url, expected));
+		let mut headers = Vec::new();
+		let range = resume.as_ref().map(|state| format!("bytes={}-", state.bytes));
+		if let (Some(state), Some(range)) = (resume.as_ref(), range.as_ref()) {
+			headers.push(("Range", range.as_str()));
+			if let Some(validator) = state.validator(url) {
+				headers.push(("If-Range", validator));
+			}
+		}
+
+		let start = Instant::now();
+		let timeout = (CONNECT_TIMEOUT_MS, READ_TIMEOUT_MS);
+		let response = match net::get_with_headers(&self.client, url, allow_insecure, timeout, &headers) {
+			Err(NetError::Http(_, 416, _)) if resume.is_some() => {
+				// The partial file does not fit the remote file, so start again.
+				PartialState::discard(dest);
+				net::get(&self.client, url, allow_insecure, timeout)?
+			}
+			response => response?,
+		};
+		self.mirrors.record_latency(url, start.elapsed());
+
+		// Servers which do not support ranges, or whose file has changed, respond with the whole file.
+		let offset = match resume {
+			Some(ref state) if response.status() == 206 => {
+				if content_range_start(&response) != Some(state.bytes) {
+					PartialState::discard(dest);
+					return Err(NetError::Network(url.to_owned(), "Unexpected Content-Range".to_owned()).into());
+				}
+				state.bytes
+			}
+			_ => 0,
+		};
+		let expected_size = response.header("content-length").and_then(|l| l.parse::<u64>().ok()).map(|l| offset + l);
+		let mut state = PartialState {
+			url: url.to_owned(),
+			bytes: offset,
+			etag: response.header("etag").map(str::to_owned),
+			last_modified: response.header("last-modified").map(str::to_owned),
+			expected: expected.cloned(),
+		};
+
+		let mut file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dest)?;
+		// Resumed downloads keep any space preallocated beyond the resume point, and are trimmed once complete.
+		if offset == 0 {
+			file.set_len(0)?;
+			if let Some(size) = job.file.size {
+				// Preallocation is only an optimisation, and is unsupported by some filesystems.
+				let _ = file.allocate(size);
+			}
+		}
+		let mut hasher = StreamHasher::new(algorithm);
+		io::copy(&mut (&mut file).take(offset), &mut hasher)?;
+		file.seek(SeekFrom::Start(offset))?;
+		let mut writer = BufWriter::new(file);
+		let mut reader = response.into_reader();
+		let mut buffer = vec![0u8; DOWNLOAD_BUFFER_SIZE];
+		let mut size = offset;
+		progress.update(position, size, 0);
+		let mut last_report = Instant::now();
+		loop {
+			let read = match reader.read(&mut buffer) {
+				Ok(read) => read,
+				Err(e) => {
+					writer.flush()?;
+					state.bytes = size;
+					state.save(dest)?;
+					return Err(NetError::Network(url.to_owned(), e.to_string()).into());
+				}
+			};
+			if read == 0 {
+				break;
+			}
+			if self.session.state() != SessionState::Running {
+				// The read data is discarded, so the connection can be dropped at a known position.
+				writer.flush()?;
+				state.bytes = size;
+				state.save(dest)?;
+				return Ok(None);
+			}
+			self.throttle.throttle(read as u64);
+			writer.write_all(&buffer[..read])?;
+			hasher.update(&buffer[..read]);
+			size += read as u64;
+			progress.update(position, size, read as u64);
+			if last_report.elapsed() >= PROGRESS_INTERVAL {
+				last_report = Instant::now();
+				self.session.emit(PatchEvent::FileProgress(job.file.path.clone(), size, job.file.size));
+			}
+			if let Some(snapshot) = progress.report() {
+				self.session.emit(PatchEvent::Progress(snapshot));
+			}
+			if size - state.bytes >= STATE_SAVE_INTERVAL {
+				writer.flush()?;
+				state.bytes = size;
+				state.save(dest)?;
+			}
+		}
+		writer.flush()?;
+		if let Some(expected_size) = expected_size {
+			// Connections closed early end the body without an error.
+			if size < expected_size {
+				state.bytes = size;
+				state.save(dest)?;
+				let desc = format!("Connection closed after {} of {} bytes", size, expected_size);
+				return Err(NetError::Network(url.to_owned(), desc).into());
+			}
+		}
+		writer.get_ref().set_len(size)?;
+		PartialState::remove(dest);
+		Ok(Some((size, hasher.finish())))
+	}
+
+	/// Copies the local file at the `file://` URL `url` to `dest`, returning the number of bytes written and their
+	/// hash. Local copies are cheap to restart, so always start from the beginning and are not throttled.
+	/// Returns None if the copy was stopped as the session is no longer running.
+	/// # Arguments
+	/// * `job` - The file being copied.
+	/// * `url` - The `file://` URL to copy.
+	/// * `dest` - Path of the partial download.
+	/// * `position` - Position of the job in `progress`.
+	/// * `progress` - Progress of the set of jobs being downloaded.
+	fn fetch_local(
+		&self, job: &DownloadJob, url: &str, dest: &Path, position: usize, progress: &TransferProgress,
+	) -> Result<Option<(u64, FileHash)>, FileManagerError> {
+		let unavailable = |desc: String| FileManagerError::SourceUnavailable(url.to_owned(), desc);
+		let source = Url::parse(url).ok().and_then(|parsed| parsed.to_file_path().ok());
+		let source = source.ok_or_else(|| unavailable("Not a local file path".to_owned()))?;
+		// The source is opened first, so a missing source leaves any partial download from a remote mirror intact.
+		let mut reader = fs::File::open(&source).map_err(|e| unavailable(e.to_string()))?;
+		let algorithm = hash::strongest_hash(&job.file).map(|h| h.algorithm()).unwrap_or(HashAlgorithm::Sha256);
+
+		PartialState::remove(dest);
+		let mut writer = BufWriter::new(fs::File::create(dest)?);
+		let mut hasher = StreamHasher::new(algorithm);
+		let mut buffer = vec![0u8; DOWNLOAD_BUFFER_SIZE];
+		let mut size = 0;
+		progress.update(position, size, 0);
+		let mut last_report = Instant::now();
+		loop {
+			let read = reader.read(&mut buffer).map_err(|e| unavailable(e.to_string()))?;
+			if read == 0 {
+				break;
+			}
+			if self.session.state() != SessionState::Running {
+				return Ok(None);
+			}
+			writer.write_all(&buffer[..read])?;
+			hasher.update(&buffer[..read]);
+			size += read as u64;
+			progress.update(position, size, read as u64);
+			if last_report.elapsed() >= PROGRESS_INTERVAL {
+				last_report = Instant::now();
+				self.session.emit(PatchEvent::FileProgress(job.file.path.clone(), size, job.file.size));
+			}
+			if let Some(snapshot) = progress.report() {
+				self.session.emit(PatchEvent::Progress(snapshot));
+			}
+		}
+		writer.flush()?;
+		Ok(Some((size, hasher.finish())))
+	}
+}
+
+/// Gets the `file://` URL of the copy of `url` in the local mirror directory `dir`, if it has one.
+/// The mirror is laid out like the download server, so the copy is found at the URL's path within `dir`.
+fn local_mirror_url(dir: &Path, url: &str) -> Option<String> {
+	let parsed = Url::parse(url).ok()?;
+	let mut path = dir.to_path_buf();
+	for segment in parsed.path_segments()? {
+		let segment = percent_decode_str(segment).decode_utf8().ok()?;
+		// Dot segments are resolved when parsing, but encoded separators could still escape the mirror.
+		if segment == ".." || segment.contains('/') || segment.contains('\\') {
+			return None;
+		}
+		if !segment.is_empty() {
+			path.push(segment.as_ref());
+		}
+	}
+	match path.is_file() {
+		true => Url::from_file_path(&path).ok().map(|url| url.to_string()),
+		false => None,
+	}
+}
+
+/// Gets the first byte position of a partial response from its `Content-Range` header (ie, `bytes 100-199/200`).
+fn content_range_start(response: &ureq::Response) -> Option<u64> {
+	let range = response.header("content-range")?.trim().strip_prefix("bytes ")?;
+	range.split('-').next()?.trim().parse().ok()
+}
+
+/// Checks a downloaded file's size and hash against its manifest entry.
+fn verify(job: &DownloadJob, size: u64, actual: &FileHash) -> Result<(), FileManagerError> {
+	if let Some(expected_size) = job.file.size {
+		if expected_size != size {
+			return Err(FileManagerError::SizeMismatch(job.file.path.clone(), expected_size, size));
+		}
+	}
+	if job.verify_checksum {
+		if let Some(expected) = hash::strongest_hash(&job.file) {
+			if expected != *actual {
+				return Err(FileManagerError::HashMismatch(job.file.path.clone(), expected, actual.clone()));
+			}
+		}
+	}
+	Ok(())
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::events::EventSink;
+	use crate::file_manager::throttle::RateLimiter;
+	use crate::test_server::{TestResponse, TestServer};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::thread;
+	use std::time::Duration;
+
+	/// Config which retries without waiting, so tests of failing mirrors run quickly.
+	fn fast_retries() -> Config {
+		Config { retry: RetryPolicy { backoff_base_ms: 1, backoff_cap_ms: 1, ..Default::default() }, ..Default::default() }
+	}
+
+	fn job(server: &TestServer, path: &str, sha1: &str) -> DownloadJob {
+		DownloadJob {
+			file: ManifestFile {
+				path: path.to_owned(),
+				url: vec![server.url(&format!("/{}", path))],
+				size: Some(3),
+				md5: None,
+				sha1: Some(sha1.to_owned()),
+				sha256: None,
+			},
+			verify_checksum: true,
+			allow_insecure: true,
+		}
+	}
+
+	#[test]
+	fn should_download_in_parallel() {
+		let server = TestServer::start(|request| match request.path.as_str() {
+			"/missing.bin" => TestResponse::status(404),
+			"/corrupt.bin" => TestResponse::ok(b"abd"),
+			_ => TestResponse::ok(b"abc"),
+		});
+		let dir = tempfile::tempdir().unwrap();
+		let downloader = Downloader::new(&Config::default(), dir.path()).unwrap();
+		let abc = "a9993e364706816aba3e25717850c26c9cd0d89d";
+		let mut jobs: Vec<DownloadJob> = (0..8).map(|i| job(&server, &format!("{}.bin", i), abc)).collect();
+		jobs.push(job(&server, "missing.bin", abc));
+		jobs.push(job(&server, "corrupt.bin", abc));
+
+		let results = downloader.download(jobs);
+
+		assert_eq!(results.len(), 10);
+		for (i, result) in results.iter().take(8).enumerate() {
+			assert_eq!(result.path, format!("{}.bin", i));
+			let downloaded = result.result.as_ref().unwrap();
+			assert_eq!(fs::read(&downloaded.temp_path).unwrap(), b"abc");
+			assert_eq!(downloaded.hash.value(), abc);
+		}
+		match results[8].result {
+			Err(FileManagerError::MirrorsExhausted(_, ref failures)) => match failures[..] {
+				[(_, FileManagerError::Net(NetError::Http(_, 404, _)))] => (),
+				ref other => panic!("Unexpected failures: {:?}", other),
+			},
+			ref other => panic!("Unexpected result: {:?}", other),
+		}
+		match results[9].result {
+			Err(FileManagerError::MirrorsExhausted(_, ref failures)) => match failures[..] {
+				[(_, FileManagerError::HashMismatch(ref path, _, _))] => assert_eq!(path, "corrupt.bin"),
+				ref other => panic!("Unexpected failures: {:?}", other),
+			},
+			ref other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn should_share_workers_between_downloaders() {
+		let (active, busiest) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+		let (counted, peak) = (active.clone(), busiest.clone());
+		let server = TestServer::start(move |_| {
+			peak.fetch_max(counted.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+			thread::sleep(Duration::from_millis(50));
+			counted.fetch_sub(1, Ordering::SeqCst);
+			TestResponse::ok(b"abc")
+		});
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config { maximum_parallel_files: 2, ..Default::default() };
+		let first = Downloader::new(&config, &dir.path().join("first")).unwrap();
+		let second = Downloader::new(&config, &dir.path().join("second")).unwrap();
+		let abc = "a9993e364706816aba3e25717850c26c9cd0d89d";
+		let jobs = |prefix: &str| (0..3).map(|i| job(&server, &format!("{}{}.bin", prefix, i), abc)).collect();
+
+		let results = Downloader::download_shared(vec![(&first, jobs("a")), (&second, jobs("b"))]);
+
+		assert_eq!(busiest.load(Ordering::SeqCst), 2);
+		assert_eq!(results.len(), 2);
+		for (results, (prefix, temp_dir)) in results.iter().zip(&[("a", "first"), ("b", "second")]) {
+			let paths: Vec<String> = (0..3).map(|i| format!("{}{}.bin", prefix, i)).collect();
+			assert_eq!(results.iter().map(|download| download.path.clone()).collect::<Vec<_>>(), paths);
+			for download in results {
+				assert!(download.result.as_ref().unwrap().temp_path.starts_with(dir.path().join(temp_dir)));
+			}
+		}
+	}
+
+	#[test]
+	fn should_fail_over_mirrors() {
+		let server = TestServer::start(|request| match request.path.as_str() {
+			"/down/app.exe" => TestResponse::status(503),
+			"/corrupt/app.exe" => TestResponse::ok(b"abd"),
+			_ => TestResponse::ok(b"abc"),
+		});
+		let dir = tempfile::tempdir().unwrap();
+		let downloader = Downloader::new(&fast_retries(), dir.path()).unwrap();
+		let mut job = job(&server, "app.exe", "a9993e364706816aba3e25717850c26c9cd0d89d");
+		job.file.url = vec![
+			"http://127.0.0.1:1/app.exe".to_owned(),
+			server.url("/down/app.exe"),
+			server.url("/corrupt/app.exe"),
+			server.url("/good/app.exe"),
+		];
+
+		let downloaded = downloader.download_file(&job).unwrap();
+
+		assert_eq!(downloaded.url, server.url("/good/app.exe"));
+		assert_eq!(fs::read(&downloaded.temp_path).unwrap(), b"abc");
+		// Unavailable mirrors are retried before failing over, corrupt copies are not.
+		assert_eq!(server.hits("/down/app.exe"), 3);
+		assert_eq!(server.hits("/corrupt/app.exe"), 1);
+	}
+
+	#[test]
+	fn should_use_local_sources() {
+		let server = TestServer::start(|_| TestResponse::ok(b"abc"));
+		let dir = tempfile::tempdir().unwrap();
+		let mirror = dir.path().join("usb");
+		fs::create_dir_all(mirror.join("patch files")).unwrap();
+		fs::write(mirror.join("patch files/app.exe"), b"abc").unwrap();
+		fs::write(mirror.join("patch files/corrupt.exe"), b"abd").unwrap();
+		let config = Config { local_mirror: Some(mirror.to_string_lossy().into_owned()), ..Default::default() };
+		let downloader = Downloader::new(&config, &dir.path().join("tmp")).unwrap();
+		let abc = "a9993e364706816aba3e25717850c26c9cd0d89d";
+
+		let mut mirrored = job(&server, "app.exe", abc);
+		mirrored.file.url = vec![server.url("/patch%20files/app.exe")];
+		let downloaded = downloader.download_file(&mirrored).unwrap();
+		assert!(net::is_local(&downloaded.url));
+		assert_eq!(fs::read(&downloaded.temp_path).unwrap(), b"abc");
+
+		// Local sources are verified, and fail over to the next source.
+		let mut corrupt = job(&server, "corrupt.exe", abc);
+		corrupt.file.url = vec![server.url("/patch%20files/corrupt.exe")];
+		let downloaded = downloader.download_file(&corrupt).unwrap();
+		assert_eq!(downloaded.url, server.url("/patch%20files/corrupt.exe"));
+
+		let mut file_url = job(&server, "other.exe", abc);
+		let source = Url::from_file_path(mirror.join("patch files/app.exe")).unwrap().to_string();
+		file_url.file.url = vec![source.clone(), server.url("/other.exe")];
+		file_url.allow_insecure = false;
+		assert_eq!(downloader.download_file(&file_url).unwrap().url, source);
+
+		let missing = Url::from_file_path(mirror.join("missing.exe")).unwrap().to_string();
+		file_url.file.url = vec![missing];
+		match downloader.download_file(&file_url) {
+			Err(FileManagerError::MirrorsExhausted(_, ref failures)) => match failures[..] {
+				[(_, FileManagerError::SourceUnavailable(_, _))] => (),
+				ref other => panic!("Unexpected failures: {:?}", other),
+			},
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		assert_eq!(server.requests().len(), 1);
+		assert_eq!(local_mirror_url(&mirror, &server.url("/%2E%2E/usb/patch%20files/app.exe")), None);
+	}
+
+	#[test]
+	fn should_retry_transient_errors() {
+		let attempts = Arc::new(AtomicUsize::new(0));
+		let counter = attempts.clone();
+		let server = TestServer::start(move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+			0 | 1 => TestResponse::status(503).header("Retry-After", "0"),
+			_ => TestResponse::ok(b"abc"),
+		});
+		let dir = tempfile::tempdir().unwrap();
+		let mut downloader = Downloader::new(&Config::default(), dir.path()).unwrap();
+		let (events, received) = EventSink::channel();
+		downloader.set_session(PatchSession::new(events));
+		let job = job(&server, "app.exe", "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+		let downloaded = downloader.download_file(&job).unwrap();
+
+		assert_eq!(fs::read(&downloaded.temp_path).unwrap(), b"abc");
+		// Retry-After is honoured instead of backing off.
+		let retries: Vec<(u32, Duration)> = received
+			.try_iter()
+			.filter_map(|event| match event {
+				PatchEvent::Retrying(_, attempt, delay, _) => Some((attempt, delay)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(retries, vec![(1, Duration::from_secs(0)), (2, Duration::from_secs(0))]);
+	}
+
+	#[test]
+	fn should_only_use_secure_mirrors() {
+		let server = TestServer::start(|_| TestResponse::ok(b"abc"));
+		let dir = tempfile::tempdir().unwrap();
+		let downloader = Downloader::new(&fast_retries(), dir.path()).unwrap();
+		let mut job = job(&server, "app.exe", "a9993e364706816aba3e25717850c26c9cd0d89d");
+		job.allow_insecure = false;
+
+		match downloader.download_file(&job) {
+			Err(FileManagerError::NoSecureMirrors(ref path)) => assert_eq!(path, "app.exe"),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		assert!(server.requests().is_empty());
+
+		// Insecure mirrors are skipped, leaving only the (unreachable) https mirror.
+		job.file.url.push("https://localhost:1/app.exe".to_owned());
+		match downloader.download_file(&job) {
+			Err(FileManagerError::MirrorsExhausted(_, ref failures)) => {
+				assert_eq!(failures.len(), 1);
+				assert_eq!(failures[0].0, "https://localhost:1/app.exe");
+			}
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		assert!(server.requests().is_empty());
+	}
+
+	#[test]
+	fn should_resume_downloads() {
+		let server = TestServer::start(|request| match request.headers.get("range").map(String::as_str) {
+			// The first response is cut short after three bytes.
+			None => TestResponse::ok(b"abc").header("Content-Length", "6").header("ETag", "\"v1\""),
+			Some("bytes=3-") if request.headers.get("if-range").map(String::as_str) == Some("\"v1\"") => {
+				TestResponse::ok(b"def").header("Content-Range", "bytes 3-5/6").with_status(206)
+			}
+			Some(_) => TestResponse::status(500),
+		});
+		let dir = tempfile::tempdir().unwrap();
+		let mut job = job(&server, "app.exe", "1f8ac10f23c5b5bc1167bda84b833e5c057a77d2");
+		job.file.size = Some(6);
+
+		// Without retries, so the interrupted transfer is left for the next downloader.
+		let no_retries = Config { retry: RetryPolicy { max_attempts: 1, ..Default::default() }, ..Default::default() };
+		let first = Downloader::new(&no_retries, dir.path()).unwrap();
+		match first.download_file(&job) {
+			Err(FileManagerError::MirrorsExhausted(_, ref failures)) => match failures[..] {
+				[(_, FileManagerError::Net(NetError::Network(_, _)))] => (),
+				ref other => panic!("Unexpected failures: {:?}", other),
+			},
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		let temp_path = first.temp_path(&job.file);
+		assert_eq!(PartialState::load(&temp_path).unwrap().bytes, 3);
+
+		// A new downloader, as after a restart, resumes from the partial file.
+		let second = Downloader::new(&Config::default(), dir.path()).unwrap();
+		let downloaded = second.download_file(&job).unwrap();
+
+		assert_eq!(fs::read(&downloaded.temp_path).unwrap(), b"abcdef");
+		assert_eq!(downloaded.hash.value(), "1f8ac10f23c5b5bc1167bda84b833e5c057a77d2");
+		assert!(!PartialState::path(&temp_path).exists());
+		assert_eq!(server.requests().len(), 2);
+	}
+
+	#[test]
+	fn should_pause_and_cancel_downloads() {
+		let body: Arc<Vec<u8>> = Arc::new((0..200_000u32).map(|i| i as u8).collect());
+		let mut hasher = StreamHasher::new(HashAlgorithm::Sha1);
+		hasher.update(&body);
+		let sha1 = hasher.finish().value().to_owned();
+		let served = body.clone();
+		let server = TestServer::start(move |request| {
+			let start = match request.headers.get("range") {
+				Some(range) => range.trim_start_matches("bytes=").trim_end_matches('-').parse().unwrap(),
+				None => 0,
+			};
+			let response = TestResponse::ok(&served[start..]).header("ETag", "\"v1\"");
+			match start {
+				0 => response,
+				_ => response.header("Content-Range", &format!("bytes {}-{}/{}", start, served.len() - 1, served.len())).with_status(206),
+			}
+		});
+		let dir = tempfile::tempdir().unwrap();
+		let mut job = job(&server, "app.exe", &sha1);
+		job.file.size = Some(body.len() as u64);
+
+		// Throttled so the transfer takes about a second, and is still running when paused. The pause outlasts it.
+		let mut downloader = Downloader::new(&Config::default(), dir.path()).unwrap();
+		let limiter = Arc::new(RateLimiter::new(100_000));
+		downloader.set_throttle(Throttle::new(vec![limiter], Vec::new()).unwrap());
+		let session = PatchSession::default();
+		downloader.set_session(session.clone());
+		let control = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(200));
+			session.pause();
+			thread::sleep(Duration::from_millis(1000));
+			session.resume();
+		});
+		let downloaded = downloader.download_file(&job).unwrap();
+		control.join().unwrap();
+
+		// The paused transfer is dropped, and resumed from where it stopped.
+		assert_eq!(fs::read(&downloaded.temp_path).unwrap(), *body);
+		let requests = server.requests();
+		assert_eq!(requests.len(), 2);
+		assert!(requests[1].headers.contains_key("range"));
+
+		let session = PatchSession::default();
+		session.cancel();
+		downloader.set_session(session);
+		match downloader.download_file(&job) {
+			Err(FileManagerError::Cancelled) => assert_eq!(server.requests().len(), 2),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,339 @@
+// --- Imports
+use super::hash::FileHash;
+use super::index::FileIndex;
+use super::rollback;
+use super::store::ContentStore;
+use super::{FileManagerError, STATE_DIR_NAME};
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// --- Consts
+/// Name of the directory within the content store holding the blob references of each application path.
+pub const REFS_DIR_NAME: &str = "refs";
+/// Default age below which unreferenced blobs are kept, so blobs of an install in progress are never collected.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Defines the store blobs used by an application path.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlobReferences {
+	/// The application path, canonicalized.
+	pub application_path: String,
+	/// Blobs used by the installed files, and by the replaced files kept for rollback.
+	#[serde(rename = "blob", skip_serializing_if = "Vec::is_empty")]
+	pub blobs: Vec<FileHash>,
+}
+
+/// Defines options for `collect_garbage`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcOptions {
+	/// If true, unreferenced blobs are reported but not removed.
+	pub dry_run: bool,
+	/// Unreferenced blobs modified more recently than this are kept.
+	pub grace_period: Duration,
+	/// If true, the references of recorded application paths which no longer exist are dropped, so their blobs may
+	/// be collected. By default they are kept, as the path may be on a drive or network share which is not mounted.
+	pub drop_missing: bool,
+}
+
+impl Default for GcOptions {
+	fn default() -> GcOptions {
+		GcOptions { dry_run: false, grace_period: DEFAULT_GRACE_PERIOD, drop_missing: false }
+	}
+}
+
+/// Defines an unreferenced blob found by `collect_garbage`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnreferencedBlob {
+	pub hash: FileHash,
+	/// Size of the blob in bytes.
+	pub size: u64,
+}
+
+/// Defines the outcome of a garbage collection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GcReport {
+	/// True if nothing was removed.
+	pub dry_run: bool,
+	/// Number of blobs referenced by an application path.
+	pub referenced: usize,
+	/// Number of unreferenced blobs kept as they are within the grace period.
+	pub recent: usize,
+	/// Unreferenced blobs outside the grace period, which were removed unless this was a dry run.
+	pub removed: Vec<UnreferencedBlob>,
+	/// Total size of the removed blobs, in bytes.
+	pub removed_bytes: u64,
+	/// Recorded application paths which do not exist. Their recorded references are kept, unless dropped with
+	/// `GcOptions.drop_missing`.
+	pub stale_paths: Vec<String>,
+}
+
+/// Records the blobs used by an application path in the content store, so they are kept by `collect_garbage`.
+/// Called after every install with symlinked storage.
+/// # Arguments
+/// * `store` - The content store.
+/// * `application_path` - The application path.
+pub fn record_references(store: &ContentStore, application_path: &Path) -> Result<(), FileManagerError> {
+	let application_path = application_path.canonicalize()?;
+	let references = find_references(store, &application_path)?;
+	save_references(store, &application_path, &references)
+}
+
+/// Removes blobs from the content store configured in `config` which no application path uses.
+/// Application paths are those recorded by `record_references`, and those of `Config.manifests`. The references of
+/// each are found again before collecting, from its file index and the links to the store among its installed files
+/// and the files kept for rollback, so files changed since the last install are accounted for. Application paths
+/// which do not exist keep the references last recorded for them, unless `GcOptions.drop_missing` is set.
+/// Blobs modified within the grace period are always kept, so blobs stored by an install in progress are safe.
+/// # Arguments
+/// * `config` - Application config.
+/// * `options` - Whether to remove blobs, the grace period, and whether to drop missing application paths.
+pub fn collect_garbage(config: &Config, options: GcOptions) -> Result<GcReport, FileManagerError> {
+	let store = ContentStore::from_config(config)?;
+	// Application paths, with the path and references of their record, if any.
+	let mut application_paths: BTreeMap<PathBuf, Option<(PathBuf, Vec<FileHash>)>> = BTreeMap::new();
+	for (record_path, references) in load_references(&store)? {
+		application_paths.insert(PathBuf::from(references.application_path), Some((record_path, references.blobs)));
+	}
+	for manifest_config in config.manifests.iter().filter(|manifest_config| !manifest_config.url.is_empty()) {
+		if let Ok(application_path) = Path::new(&manifest_config.application_path).canonicalize() {
+			application_paths.entry(application_path).or_insert(None);
+		}
+	}
+
+	let mut report = GcReport { dry_run: options.dry_run, ..Default::default() };
+	let mut referenced = HashSet::new();
+	for (application_path, record) in application_paths {
+		if !application_path.is_dir() {
+			report.stale_paths.push(application_path.to_string_lossy().into_owned());
+			match (options.drop_missing, record) {
+				(true, Some((record_path, _))) if !options.dry_run => fs::remove_file(record_path)?,
+				(false, Some((_, blobs))) => referenced.extend(blobs),
+				_ => (),
+			}
+			continue;
+		}
+		let references = find_references(&store, &application_path)?;
+		if !options.dry_run {
+			save_references(&store, &application_path, &references)?;
+		}
+		referenced.extend(references);
+	}
+
+	let now = SystemTime::now();
+	for (hash, blob_path) in store.blobs()? {
+		if referenced.contains(&hash) {
+			report.referenced += 1;
+			continue;
+		}
+		let metadata = fs::metadata(&blob_path)?;
+		let age = metadata.modified().ok().and_then(|modified| now.duration_since(modified).ok());
+		if age.is_none_or(|age| age < options.grace_period) {
+			report.recent += 1;
+			continue;
+		}
+		if !options.dry_run {
+			store.remove(&hash)?;
+		}
+		report.removed_bytes += metadata.len();
+		report.removed.push(UnreferencedBlob { hash, size: metadata.len() });
+	}
+	Ok(report)
+}
+
+/// Finds the blobs used by an application path: the hashes in its file index, and the blobs linked to by its files,
+/// including the replaced files kept for rollback.
+fn find_references(store: &ContentStore, application_path: &Path) -> Result<HashSet<FileHash>, FileManagerError> {
+	let mut references: HashSet<FileHash> =
+		FileIndex::load(application_path).entries.into_values().map(|entry| entry.hash).collect();
+	find_links(store, application_path, &mut references)?;
+	let rollback_dir = rollback::get_rollback_dir(application_path);
+	if rollback_dir.is_dir() {
+		find_links(store, &rollback_dir, &mut references)?;
+	}
+	Ok(references)
+}
+
+/// Recursively collects the blobs linked to by symlinks under `dir`.
+/// The state directory is skipped, as is the content store if it is within `dir`. Symlinked directories are not
+/// followed.
+fn find_links(store: &ContentStore, dir: &Path, references: &mut HashSet<FileHash>) -> Result<(), FileManagerError> {
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let path = entry.path();
+		let file_type = entry.file_type()?;
+		if file_type.is_dir() {
+			if entry.file_name() != STATE_DIR_NAME && path != store.root() {
+				find_links(store, &path, references)?;
+			}
+		} else if file_type.is_symlink() {
+			if let Some(hash) = fs::read_link(&path).ok().and_then(|target| store.blob_hash(&target)) {
+				references.insert(hash);
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Gets the path of the references of an application path, named by the SHA-256 hash of the path.
+fn references_path(store: &ContentStore, application_path: &Path) -> PathBuf {
+	let digest = Sha256::digest(application_path.to_string_lossy().as_bytes());
+	store.root().join(REFS_DIR_NAME).join(format!("{}.toml", hex::encode(digest)))
+}
+
+/// Saves the references of an application path, replacing any previous references.
+fn save_references(
+	store: &ContentStore, application_path: &Path, references: &HashSet<FileHash>,
+) -> Result<(), FileManagerError> {
+	let mut blobs: Vec<FileHash> = references.iter().cloned().collect();
+	blobs.sort_by(|a, b| (a.algorithm(), a.value()).cmp(&(b.algorithm(), b.value())));
+	let references = BlobReferences { application_path: application_path.to_string_lossy().into_owned(), blobs };
+	let path = references_path(store, application_path);
+	fs::create_dir_all(path.parent().unwrap_or_else(|| store.root()))?;
+	let contents = toml::to_string(&references).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	// Written via a temporary file, so an interrupted save can not leave truncated references.
+	let temp_path = path.with_extension("toml.tmp");
+	fs::write(&temp_path, contents)?;
+	fs::rename(&temp_path, &path)?;
+	Ok(())
+}
+
+/// Loads the recorded references of every application path, with the path of each record.
+/// Unreadable records are skipped; their application paths are still found if they are configured.
+fn load_references(store: &ContentStore) -> Result<Vec<(PathBuf, BlobReferences)>, FileManagerError> {
+	let refs_dir = store.root().join(REFS_DIR_NAME);
+	let mut records = Vec::new();
+	if !refs_dir.is_dir() {
+		return Ok(records);
+	}
+	for entry in fs::read_dir(&refs_dir)? {
+		let path = entry?.path();
+		if path.extension().is_none_or(|extension| extension != "toml") {
+			continue;
+		}
+		match fs::read_to_string(&path).ok().and_then(|contents| toml::from_str::<BlobReferences>(&contents).ok()) {
+			Some(references) => records.push((path, references)),
+			None => log::warn!("Skipping unreadable blob references {}", path.display()),
+		}
+	}
+	Ok(records)
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::file_manager::hash::HashAlgorithm;
+	use crate::file_manager::rollback::Transaction;
+	use crate::test_fixtures;
+
+	fn store_blob(store: &ContentStore, dir: &Path, contents: &str, sha1: &str) -> FileHash {
+		let hash = FileHash::new(HashAlgorithm::Sha1, sha1).unwrap();
+		let download = dir.join("download.tmp");
+		fs::write(&download, contents).unwrap();
+		store.insert(&hash, &download).unwrap();
+		hash
+	}
+
+	#[test]
+	fn should_collect_unreferenced_blobs() {
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config {
+			use_symlinked_storage: true,
+			storage_path: Some(dir.path().join("store").to_string_lossy().into_owned()),
+			..Default::default()
+		};
+		let store = ContentStore::from_config(&config).unwrap();
+		let installed = store_blob(&store, dir.path(), "abc", "a9993e364706816aba3e25717850c26c9cd0d89d");
+		let replaced = store_blob(&store, dir.path(), "xyz", "66b27417d37e024c46526c2f6d358a754fc552f3");
+		let removed = store_blob(&store, dir.path(), "old", "c00dbbc9dadfbe1e232e93a729dd4752fade0abf");
+		let other = store_blob(&store, dir.path(), "other", "d0941e68da8f38151ff86a61fc59f7c5cf9fcaa2");
+
+		// The app's installed file links to one blob, and the file it replaced, kept for rollback, to another.
+		let app = dir.path().join("app");
+		store.materialize(&replaced, &app.join("bin/app.exe")).unwrap();
+		let transaction = Transaction::begin(&app, &["bin/app.exe".to_owned()]).unwrap();
+		transaction.back_up("bin/app.exe").unwrap();
+		store.materialize(&installed, &app.join("bin/app.exe")).unwrap();
+		transaction.commit().unwrap();
+		record_references(&store, &app).unwrap();
+		// Another app linked the last blob, and has since been deleted.
+		let deleted_app = dir.path().join("deleted");
+		store.materialize(&other, &deleted_app.join("other.exe")).unwrap();
+		record_references(&store, &deleted_app).unwrap();
+		fs::remove_dir_all(&deleted_app).unwrap();
+
+		let recent = collect_garbage(&config, GcOptions::default()).unwrap();
+		assert_eq!((recent.referenced, recent.recent, recent.removed.len()), (3, 1, 0));
+		assert_eq!(recent.stale_paths.len(), 1);
+
+		let options = GcOptions { dry_run: true, grace_period: Duration::from_secs(0), drop_missing: true };
+		let dry_run = collect_garbage(&config, options).unwrap();
+		let mut reported: Vec<FileHash> = dry_run.removed.iter().map(|blob| blob.hash.clone()).collect();
+		reported.sort_by(|a, b| a.value().cmp(b.value()));
+		assert_eq!(reported, vec![removed.clone(), other.clone()]);
+		assert_eq!(dry_run.removed_bytes, 8);
+		assert!(store.contains(&removed) && store.contains(&other));
+		assert_eq!(load_references(&store).unwrap().len(), 2);
+
+		let collected = collect_garbage(&config, GcOptions { dry_run: false, ..options }).unwrap();
+		assert_eq!(collected.removed.len(), 2);
+		assert!(store.contains(&installed) && store.contains(&replaced));
+		assert!(!store.contains(&removed) && !store.contains(&other));
+		assert_eq!(load_references(&store).unwrap().len(), 1);
+		assert_eq!(rollback::rollback(&app).unwrap(), vec!["bin/app.exe"]);
+		assert_eq!(fs::read(app.join("bin/app.exe")).unwrap(), b"xyz");
+
+		// Configured application paths are kept even without recorded references.
+		let configured = dir.path().join("configured");
+		store.materialize(&installed, &configured.join("app.exe")).unwrap();
+		fs::remove_dir_all(&app).unwrap();
+		let manifest_config = test_fixtures::manifest_config("https://cdn.example.com/Manifest.toml", &configured);
+		let config = Config { manifests: vec![manifest_config], ..config };
+		let collected = collect_garbage(&config, GcOptions { dry_run: false, ..options }).unwrap();
+		assert_eq!(collected.removed.iter().map(|blob| blob.hash.clone()).collect::<Vec<_>>(), vec![replaced]);
+		assert!(store.contains(&installed));
+		// The references of configured application paths are recorded, and those of missing ones dropped.
+		let records = load_references(&store).unwrap();
+		assert_eq!(records.len(), 1);
+		assert_eq!(records[0].1.blobs, vec![installed]);
+	}
+
+	#[test]
+	fn should_keep_references_of_missing_paths() {
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config {
+			use_symlinked_storage: true,
+			storage_path: Some(dir.path().join("store").to_string_lossy().into_owned()),
+			..Default::default()
+		};
+		let store = ContentStore::from_config(&config).unwrap();
+		let installed = store_blob(&store, dir.path(), "abc", "a9993e364706816aba3e25717850c26c9cd0d89d");
+		let app = dir.path().join("app");
+		store.materialize(&installed, &app.join("app.exe")).unwrap();
+		record_references(&store, &app).unwrap();
+		let recorded = app.canonicalize().unwrap().to_string_lossy().into_owned();
+
+		// The drive holding the app is unmounted.
+		let unmounted = dir.path().join("unmounted");
+		fs::rename(&app, &unmounted).unwrap();
+		let options = GcOptions { grace_period: Duration::from_secs(0), ..Default::default() };
+		let collected = collect_garbage(&config, options).unwrap();
+		assert_eq!(collected.stale_paths, vec![recorded]);
+		assert_eq!((collected.referenced, collected.removed.len()), (1, 0));
+		assert!(store.contains(&installed));
+		assert_eq!(load_references(&store).unwrap().len(), 1);
+
+		fs::rename(&unmounted, &app).unwrap();
+		let collected = collect_garbage(&config, options).unwrap();
+		assert!(collected.stale_paths.is_empty());
+		assert_eq!(fs::read(app.join("app.exe")).unwrap(), b"abc");
+	}
+}
@@ -0,0 +1,224 @@
+// --- Imports
+use crate::manifest::manifest_spec::ManifestFile;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// --- Consts
+/// Read buffer size used when hashing files on disk.
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Defines a hash algorithm supported in manifests, ordered from weakest to strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+	Md5,
+	Sha1,
+	Sha256,
+}
+impl HashAlgorithm {
+	/// Length of the algorithm's digest, in hex characters.
+	pub fn hex_len(self) -> usize {
+		match self {
+			HashAlgorithm::Md5 => 32,
+			HashAlgorithm::Sha1 => 40,
+			HashAlgorithm::Sha256 => 64,
+		}
+	}
+}
+impl fmt::Display for HashAlgorithm {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			HashAlgorithm::Md5 => write!(f, "md5"),
+			HashAlgorithm::Sha1 => write!(f, "sha1"),
+			HashAlgorithm::Sha256 => write!(f, "sha256"),
+		}
+	}
+}
+
+/// Defines a file digest. Values are always stored as lowercase hex, and are validated on creation so a
+/// `FileHash` is always safe to use as a file name. Deserialized hashes are validated the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "RawFileHash")]
+pub struct FileHash {
+	/// Algorithm used to produce the digest.
+	algorithm: HashAlgorithm,
+	/// Lowercase hex digest.
+	value: String,
+}
+impl FileHash {
+	/// Creates a `FileHash`, returning `None` if `value` is not a valid hex digest for `algorithm`.
+	/// # Arguments
+	/// * `algorithm` - The hash algorithm.
+	/// * `value` - The hex digest, in any case.
+	pub fn new(algorithm: HashAlgorithm, value: &str) -> Option<FileHash> {
+		if value.len() != algorithm.hex_len() || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+			return None;
+		}
+		Some(FileHash { algorithm, value: value.to_ascii_lowercase() })
+	}
+
+	/// Gets the algorithm used to produce the digest.
+	pub fn algorithm(&self) -> HashAlgorithm {
+		self.algorithm
+	}
+
+	/// Gets the lowercase hex digest.
+	pub fn value(&self) -> &str {
+		&self.value
+	}
+}
+/// Unvalidated form of a `FileHash`, as read from disk.
+#[derive(Deserialize)]
+struct RawFileHash {
+	algorithm: HashAlgorithm,
+	value: String,
+}
+impl TryFrom<RawFileHash> for FileHash {
+	type Error = String;
+	fn try_from(item: RawFileHash) -> Result<Self, Self::Error> {
+		FileHash::new(item.algorithm, &item.value)
+			.ok_or_else(|| format!("invalid {} digest: {:?}", item.algorithm, item.value))
+	}
+}
+impl fmt::Display for FileHash {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.algorithm, self.value)
+	}
+}
+
+/// Returns the strongest valid hash provided for a manifest file, if any.
+/// Malformed hash values are ignored, so a file with only a malformed hash has no usable hash.
+/// # Arguments
+/// * `file` - The ManifestFile.
+pub fn strongest_hash(file: &ManifestFile) -> Option<FileHash> {
+	let candidates = [
+		(HashAlgorithm::Sha256, &file.sha256),
+		(HashAlgorithm::Sha1, &file.sha1),
+		(HashAlgorithm::Md5, &file.md5),
+	];
+	candidates.iter().find_map(|(algorithm, value)| value.as_deref().and_then(|v| FileHash::new(*algorithm, v)))
+}
+
+/// Incremental hasher for a single algorithm. Implements `Write` so it may be fed directly from
+/// `io::copy` or tee'd alongside a file while downloading.
+pub struct StreamHasher {
+	inner: HasherInner,
+}
+enum HasherInner {
+	Md5(Md5),
+	Sha1(Sha1),
+	Sha256(Sha256),
+}
+impl StreamHasher {
+	/// Creates a new hasher for `algorithm`.
+	pub fn new(algorithm: HashAlgorithm) -> StreamHasher {
+		let inner = match algorithm {
+			HashAlgorithm::Md5 => HasherInner::Md5(Md5::new()),
+			HashAlgorithm::Sha1 => HasherInner::Sha1(Sha1::new()),
+			HashAlgorithm::Sha256 => HasherInner::Sha256(Sha256::new()),
+		};
+		StreamHasher { inner }
+	}
+
+	/// Feeds `data` into the hasher.
+	pub fn update(&mut self, data: &[u8]) {
+		match self.inner {
+			HasherInner::Md5(ref mut h) => h.input(data),
+			HasherInner::Sha1(ref mut h) => h.input(data),
+			HasherInner::Sha256(ref mut h) => h.input(data),
+		}
+	}
+
+	/// Consumes the hasher, returning the digest.
+	pub fn finish(self) -> FileHash {
+		let (algorithm, value) = match self.inner {
+			HasherInner::Md5(h) => (HashAlgorithm::Md5, hex::encode(h.result())),
+			HasherInner::Sha1(h) => (HashAlgorithm::Sha1, hex::encode(h.result())),
+			HasherInner::Sha256(h) => (HashAlgorithm::Sha256, hex::encode(h.result())),
+		};
+		FileHash { algorithm, value }
+	}
+}
+impl Write for StreamHasher {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.update(buf);
+		Ok(buf.len())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Hashes the contents of a file on disk.
+/// # Arguments
+/// * `path` - Path to the file.
+/// * `algorithm` - The hash algorithm to use.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+	let mut file = fs::File::open(path)?;
+	let mut hasher = StreamHasher::new(algorithm);
+	let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+	loop {
+		let read = file.read(&mut buffer)?;
+		if read == 0 {
+			break;
+		}
+		hasher.update(&buffer[..read]);
+	}
+	Ok(hasher.finish())
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_hash() {
+		let digest = |algorithm| {
+			let mut hasher = StreamHasher::new(algorithm);
+			hasher.write_all(b"abc").unwrap();
+			hasher.finish().to_string()
+		};
+		assert_eq!(digest(HashAlgorithm::Md5), "md5:900150983cd24fb0d6963f7d28e17f72");
+		assert_eq!(digest(HashAlgorithm::Sha1), "sha1:a9993e364706816aba3e25717850c26c9cd0d89d");
+		assert_eq!(
+			digest(HashAlgorithm::Sha256),
+			"sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+		);
+	}
+
+	#[test]
+	fn should_pick_strongest_valid_hash() {
+		let mut file = ManifestFile {
+			path: "app.exe".to_owned(),
+			url: Vec::new(),
+			size: None,
+			md5: Some("900150983CD24FB0D6963F7D28E17F72".to_owned()),
+			sha1: Some("a9993e364706816aba3e25717850c26c9cd0d89d".to_owned()),
+			sha256: Some("../../../../etc/passwd".to_owned()),
+		};
+		assert_eq!(strongest_hash(&file).unwrap().algorithm(), HashAlgorithm::Sha1);
+		file.sha1 = None;
+		assert_eq!(strongest_hash(&file).unwrap().value(), "900150983cd24fb0d6963f7d28e17f72");
+		file.md5 = None;
+		assert!(strongest_hash(&file).is_none());
+	}
+
+	#[test]
+	fn should_validate_deserialized_hashes() {
+		let document = "algorithm = \"md5\"\nvalue = \"900150983CD24FB0D6963F7D28E17F72\"";
+		let hash: FileHash = toml::from_str(document).unwrap();
+		assert_eq!(hash.value(), "900150983cd24fb0d6963f7d28e17f72");
+		assert!(toml::from_str::<FileHash>("algorithm = \"sha1\"\nvalue = \"../../../../etc/passwd\"").is_err());
+		assert!(toml::from_str::<FileHash>("algorithm = \"sha256\"\nvalue = \"\"").is_err());
+	}
+}
@@ -0,0 +1,138 @@
+// --- Imports
+use super::hash::FileHash;
+use super::{FileManagerError, STATE_DIR_NAME};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// --- Consts
+/// Name of the local file index, within the state directory of an application path.
+pub const INDEX_FILE_NAME: &str = "index.toml";
+
+/// Defines the last verified state of an installed file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+	/// Size of the file in bytes.
+	pub size: u64,
+	/// Modification time of the file, in nanoseconds since the Unix epoch.
+	pub mtime_ns: u64,
+	/// Inode number of the file, on platforms which provide one.
+	pub inode: Option<u64>,
+	/// Hash of the file when it was last verified.
+	pub hash: FileHash,
+}
+
+impl IndexEntry {
+	/// Creates an entry for a file with the given metadata and verified hash.
+	pub fn new(metadata: &fs::Metadata, hash: FileHash) -> IndexEntry {
+		IndexEntry { size: metadata.len(), mtime_ns: mtime_ns(metadata), inode: inode(metadata), hash }
+	}
+
+	/// Returns true if a file with `metadata` appears unchanged since this entry was recorded.
+	pub fn matches(&self, metadata: &fs::Metadata) -> bool {
+		self.size == metadata.len() && self.mtime_ns == mtime_ns(metadata) && self.inode == inode(metadata)
+	}
+}
+
+/// Index of the files installed in an application path, keyed by manifest path.
+/// Files whose size, modification time and inode are unchanged since they were last verified are assumed to still
+/// match their recorded hash, so they do not need to be hashed again.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileIndex {
+	#[serde(rename = "file")]
+	pub entries: BTreeMap<String, IndexEntry>,
+}
+
+impl FileIndex {
+	/// Gets the path of the index for an application path.
+	pub fn path(application_path: &Path) -> PathBuf {
+		application_path.join(STATE_DIR_NAME).join(INDEX_FILE_NAME)
+	}
+
+	/// Loads the index of an application path.
+	/// The index is only an optimisation, so a missing or unreadable index yields an empty index.
+	pub fn load(application_path: &Path) -> FileIndex {
+		fs::read_to_string(FileIndex::path(application_path))
+			.ok()
+			.and_then(|contents| toml::from_str(&contents).ok())
+			.unwrap_or_default()
+	}
+
+	/// Saves the index of an application path, replacing any existing index.
+	pub fn save(&self, application_path: &Path) -> Result<(), FileManagerError> {
+		let path = FileIndex::path(application_path);
+		fs::create_dir_all(path.parent().unwrap_or(application_path))?;
+		let contents = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		// Written via a temporary file, so an interrupted save can not leave a truncated index.
+		let temp_path = path.with_extension("toml.tmp");
+		fs::write(&temp_path, contents)?;
+		fs::rename(&temp_path, &path)?;
+		Ok(())
+	}
+
+	/// Gets the recorded hash of the file at manifest path `path`, if it has not changed since it was verified.
+	/// # Arguments
+	/// * `path` - Manifest path of the file.
+	/// * `metadata` - Current metadata of the file.
+	pub fn unchanged_hash(&self, path: &str, metadata: &fs::Metadata) -> Option<&FileHash> {
+		self.entries.get(path).filter(|entry| entry.matches(metadata)).map(|entry| &entry.hash)
+	}
+
+	/// Records the verified hash of the file at manifest path `path`, which is on disk at `location`.
+	pub fn record(&mut self, path: &str, location: &Path, hash: FileHash) -> Result<(), FileManagerError> {
+		let metadata = fs::metadata(location)?;
+		self.entries.insert(path.to_owned(), IndexEntry::new(&metadata, hash));
+		Ok(())
+	}
+
+	/// Removes the entry for manifest path `path`.
+	pub fn remove(&mut self, path: &str) {
+		self.entries.remove(path);
+	}
+}
+
+fn mtime_ns(metadata: &fs::Metadata) -> u64 {
+	metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_nanos() as u64)
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<u64> {
+	use std::os::unix::fs::MetadataExt;
+	Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> Option<u64> {
+	None
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::file_manager::hash::HashAlgorithm;
+
+	#[test]
+	fn should_detect_changed_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let file = dir.path().join("app.exe");
+		fs::write(&file, b"abc").unwrap();
+		let hash = FileHash::new(HashAlgorithm::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+		let mut index = FileIndex::default();
+		index.record("app.exe", &file, hash.clone()).unwrap();
+		index.save(dir.path()).unwrap();
+
+		let index = FileIndex::load(dir.path());
+		assert_eq!(index.unchanged_hash("app.exe", &fs::metadata(&file).unwrap()), Some(&hash));
+		assert_eq!(index.unchanged_hash("other.exe", &fs::metadata(&file).unwrap()), None);
+
+		fs::write(&file, b"abcd").unwrap();
+		assert_eq!(index.unchanged_hash("app.exe", &fs::metadata(&file).unwrap()), None);
+	}
+}
@@ -0,0 +1,598 @@
+// --- Imports
+use super::dedup::{self, LocalFiles};
+use super::download::{DownloadJob, DownloadResult, DownloadedFile, Downloader, PARTIAL_EXTENSION};
+use super::gc;
+use super::hash::{self, FileHash, HashAlgorithm, StreamHasher};
+use super::index::FileIndex;
+use super::mirror::MirrorTracker;
+use super::rollback::Transaction;
+use super::space;
+use super::store::ContentStore;
+use super::throttle::Throttle;
+use super::{FileManagerError, STATE_DIR_NAME};
+use crate::config::{Config, ManifestConfig};
+use crate::events::{PatchEvent, Phase};
+use crate::manifest::manifest_spec::ManifestFile;
+use crate::net::client::Client;
+use crate::session::PatchSession;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+// --- Consts
+/// Name of the temporary download directory, within the state directory or content store.
+const TEMP_DIR_NAME: &str = "tmp";
+
+/// Defines a set of changes to install into the application path of a manifest. See `install_all`.
+#[derive(Debug, Clone, Copy)]
+pub struct InstallRequest<'a> {
+	/// Config of the manifest the files belong to.
+	pub manifest_config: &'a ManifestConfig,
+	/// The files to install.
+	pub files: &'a [ManifestFile],
+	/// Manifest paths of the installed files to remove.
+	pub removed: &'a [String],
+}
+
+/// Resolves a manifest file path against an application path.
+/// Manifests are untrusted, so absolute paths and paths which would escape the application path are rejected.
+/// # Arguments
+/// * `application_path` - The application path.
+/// * `relative` - The manifest file path.
+pub fn resolve_path(application_path: &Path, relative: &str) -> Result<PathBuf, FileManagerError> {
+	let relative_path = Path::new(relative);
+	let mut has_file_name = false;
+	for component in relative_path.components() {
+		match component {
+			Component::Normal(name) if name != STATE_DIR_NAME => has_file_name = true,
+			Component::CurDir => (),
+			_ => return Err(FileManagerError::UnsafePath(relative.to_owned())),
+		}
+	}
+	if !has_file_name {
+		return Err(FileManagerError::UnsafePath(relative.to_owned()));
+	}
+	Ok(application_path.join(relative_path))
+}
+
+/// Gets the directory used for temporary downloads for an application path.
+/// Downloads are kept within the content store when it is in use, so they can be moved into it without copying.
+/// Each application path has its own directory within the store, so files of the same name can be downloaded for
+/// several application paths at once.
+pub fn get_temp_dir(application_path: &Path, store: Option<&ContentStore>) -> PathBuf {
+	match store {
+		Some(store) => {
+			let mut hasher = StreamHasher::new(HashAlgorithm::Sha1);
+			hasher.update(application_path.to_string_lossy().as_bytes());
+			store.root().join(TEMP_DIR_NAME).join(hasher.finish().value())
+		}
+		None => application_path.join(STATE_DIR_NAME).join(TEMP_DIR_NAME),
+	}
+}
+
+/// Moves a downloaded file to `dest`, replacing any existing file.
+/// If a content store is provided, the file is added to it and `dest` is linked to the stored blob. Files which are
+/// already stored blobs are linked without being moved.
+/// # Arguments
+/// * `downloaded` - The downloaded file.
+/// * `dest` - Destination path.
+/// * `store` - The content store, if symlinked storage is enabled.
+pub fn place_file(downloaded: &DownloadedFile, dest: &Path, store: Option<&ContentStore>) -> Result<(), FileManagerError> {
+	if let Some(store) = store {
+		if downloaded.temp_path != store.blob_path(&downloaded.hash) {
+			store.adopt(&downloaded.hash, &downloaded.temp_path)?;
+		}
+		store.materialize(&downloaded.hash, dest)?;
+		return Ok(());
+	}
+	if let Some(parent) = dest.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	if fs::symlink_metadata(dest).is_ok() {
+		fs::remove_file(dest)?;
+	}
+	if fs::rename(&downloaded.temp_path, dest).is_err() {
+		fs::copy(&downloaded.temp_path, dest)?;
+		fs::remove_file(&downloaded.temp_path)?;
+	}
+	Ok(())
+}
+
+/// Downloads `files` and installs them into the application path of `manifest_config`.
+/// Returns one result per file, in the order given.
+/// Files with the same hash are only downloaded once. Files already in the content store are linked without being
+/// downloaded, and files matching an unchanged installed file (ie, one moved between releases) are copied from it.
+/// Before downloading, the space needed is checked against the free space of the download filesystem, failing early
+/// if there is not enough.
+/// Installs are all or nothing. Files are only swapped into the application path once every file has downloaded and
+/// verified, and if any file can not be swapped in, the files already swapped are rolled back. Replaced files are
+/// kept until the next install, so a completed install may also be rolled back with `rollback::rollback`.
+/// Downloads are limited to the global and manifest download rates, outside of the configured unthrottled windows.
+/// Measurements taken while downloading are added to `mirrors`; callers should save its statistics afterwards.
+/// Installed files are recorded in the application path's file index, so they need not be hashed when next verified.
+/// Phase changes, download progress and the outcome of each file are reported to `session`. If the session is
+/// cancelled before every file has downloaded, nothing is installed. Partial downloads are kept so they can be
+/// resumed, and with symlinked storage, completed downloads are kept in the content store. Once files start being
+/// swapped in, the install is completed regardless.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest the files belong to.
+/// * `files` - The files to install.
+/// * `mirrors` - Mirror tracker used to rank the files' mirrors.
+/// * `session` - Session the install belongs to.
+pub fn install_files(
+	config: &Config, manifest_config: &ManifestConfig, files: &[ManifestFile], mirrors: &Arc<MirrorTracker>,
+	session: &PatchSession,
+) -> Result<Vec<DownloadResult>, FileManagerError> {
+	install_changes(config, manifest_config, files, &[], mirrors, session)
+}
+
+/// Installs `files` as `install_files` does, and removes the installed files at manifest paths `removed` in the same
+/// install. Removed files are kept with the replaced files, so they are restored if the install is rolled back.
+/// Nothing is removed unless every file downloads and verifies.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest the files belong to.
+/// * `files` - The files to install.
+/// * `removed` - Manifest paths of the files to remove.
+/// * `mirrors` - Mirror tracker used to rank the files' mirrors.
+/// * `session` - Session the install belongs to.
+pub fn install_changes(
+	config: &Config, manifest_config: &ManifestConfig, files: &[ManifestFile], removed: &[String],
+	mirrors: &Arc<MirrorTracker>, session: &PatchSession,
+) -> Result<Vec<DownloadResult>, FileManagerError> {
+	let request = InstallRequest { manifest_config, files, removed };
+	install_all(config, &[request], mirrors, session).remove(0)
+}
+
+/// Installs several sets of changes as `install_changes` does, downloading the files of every set on one pool of up
+/// to `Config.maximum_parallel_files` workers. Returns one result per request, in the order given.
+/// Each install is still all or nothing on its own, so a file which fails only aborts the install it belongs to.
+/// Requests into the same application path share its index and temporary downloads, so are installed one after
+/// another.
+/// # Arguments
+/// * `config` - Application config.
+/// * `requests` - The changes to install.
+/// * `mirrors` - Mirror tracker used to rank the files' mirrors.
+/// * `session` - Session the installs belong to.
+pub fn install_all(
+	config: &Config, requests: &[InstallRequest], mirrors: &Arc<MirrorTracker>, session: &PatchSession,
+) -> Vec<Result<Vec<DownloadResult>, FileManagerError>> {
+	let mut installed = Vec::with_capacity(requests.len());
+	let mut remaining: Vec<usize> = (0..requests.len()).collect();
+	while !remaining.is_empty() {
+		let mut application_paths = HashSet::new();
+		let (round, later): (Vec<usize>, Vec<usize>) = remaining
+			.into_iter()
+			.partition(|&i| application_paths.insert(&requests[i].manifest_config.application_path));
+		let results = install_round(config, round.iter().map(|&i| requests[i]).collect(), mirrors, session);
+		installed.extend(round.into_iter().zip(results));
+		remaining = later;
+	}
+	installed.sort_by_key(|(i, _)| *i);
+	installed.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Installs requests into distinct application paths, downloading the files of every request on one pool.
+/// With symlinked storage, files with the same hash as a file of an earlier request are only downloaded by the
+/// earlier request, and are linked from the content store.
+fn install_round(
+	config: &Config, requests: Vec<InstallRequest>, mirrors: &Arc<MirrorTracker>, session: &PatchSession,
+) -> Vec<Result<Vec<DownloadResult>, FileManagerError>> {
+	let mut pending: Vec<Result<Option<PendingInstall>, FileManagerError>> =
+		requests.into_iter().map(|request| PendingInstall::prepare(config, request, mirrors, session)).collect();
+	let mut installs: Vec<&mut PendingInstall> = pending.iter_mut().flatten().flatten().collect();
+	// Jobs downloaded for more than one request, by position in `installs` and in the install's jobs.
+	let mut shared_jobs = HashSet::new();
+	let mut first_by_hash: HashMap<FileHash, (usize, usize)> = HashMap::new();
+	for (k, install) in installs.iter_mut().enumerate().filter(|(_, install)| install.store.is_some()) {
+		let jobs = mem::take(&mut install.jobs);
+		let job_positions = mem::take(&mut install.job_positions);
+		for (job, position) in jobs.into_iter().zip(job_positions) {
+			let expected = hash::strongest_hash(&job.file);
+			match expected.map(|expected| *first_by_hash.entry(expected).or_insert((k, install.jobs.len()))) {
+				Some(first) if first.0 != k => {
+					shared_jobs.insert(first);
+					install.shared.push(position);
+				}
+				_ => {
+					install.jobs.push(job);
+					install.job_positions.push(position);
+				}
+			}
+		}
+	}
+	let mut batches = Vec::with_capacity(installs.len());
+	for install in installs.iter_mut() {
+		install.downloader.probe_mirrors(&install.jobs);
+		batches.push((&install.downloader, mem::take(&mut install.jobs)));
+	}
+	if !batches.is_empty() {
+		session.emit(PatchEvent::PhaseChanged(Phase::Downloading));
+	}
+	let mut downloads = Downloader::download_shared(batches);
+	// Shared files are added to the content store straight away, so they are kept even if the install which
+	// downloaded them is aborted.
+	for &(k, j) in &shared_jobs {
+		if let (Some(store), Ok(downloaded)) = (installs[k].store.as_ref(), downloads[k][j].result.as_mut()) {
+			if let Ok(blob_path) = store.adopt(&downloaded.hash, &downloaded.temp_path) {
+				downloaded.temp_path = blob_path;
+			}
+		}
+	}
+	let mut downloads = downloads.into_iter();
+	pending
+		.into_iter()
+		.map(|install| match install? {
+			Some(install) => install.finish(downloads.next().unwrap_or_default(), session),
+			None => Ok(Vec::new()),
+		})
+		.collect()
+}
+
+/// Defines an install whose files are prepared, but not yet downloaded.
+struct PendingInstall<'a> {
+	request: InstallRequest<'a>,
+	store: Option<ContentStore>,
+	downloader: Downloader,
+	index: FileIndex,
+	/// Results of files which are not downloaded, by position in the request's files.
+	prepared: Vec<(usize, DownloadResult)>,
+	/// Positions of files with the same hash as an earlier file, and of that earlier file.
+	duplicates: HashMap<usize, usize>,
+	jobs: Vec<DownloadJob>,
+	/// Positions of the files of `jobs`.
+	job_positions: Vec<usize>,
+	/// Positions of files downloaded by an earlier install, which are installed from the content store.
+	shared: Vec<usize>,
+}
+
+impl<'a> PendingInstall<'a> {
+	/// Prepares the install of a request, finding the files which need not be downloaded and staging copies of
+	/// unchanged installed files. Returns None if the request has no changes.
+	fn prepare(
+		config: &Config, request: InstallRequest<'a>, mirrors: &Arc<MirrorTracker>, session: &PatchSession,
+	) -> Result<Option<PendingInstall<'a>>, FileManagerError> {
+		session.emit(PatchEvent::PhaseChanged(Phase::Preparing));
+		let InstallRequest { manifest_config, files, removed } = request;
+		let application_path = Path::new(&manifest_config.application_path);
+		for path in removed {
+			resolve_path(application_path, path)?;
+		}
+		let store = match config.use_symlinked_storage {
+			true => Some(ContentStore::from_config(config)?),
+			false => None,
+		};
+		let temp_dir = get_temp_dir(application_path, store.as_ref());
+		let mut downloader = Downloader::with_mirrors(config, &temp_dir, mirrors.clone())?;
+		downloader.set_client(Client::for_manifest(config, manifest_config)?);
+		downloader.set_throttle(Throttle::from_config(config, manifest_config)?);
+		downloader.set_session(session.clone());
+		let index = FileIndex::load(application_path);
+		let local = LocalFiles::new(application_path, &index);
+
+		let mut prepared = Vec::new();
+		// Installed files to copy instead of downloading, by position in `files`.
+		let mut local_copies = Vec::new();
+		let mut duplicates = HashMap::new();
+		let mut first_by_hash: HashMap<FileHash, usize> = HashMap::new();
+		let mut jobs = Vec::with_capacity(files.len());
+		let mut job_positions = Vec::with_capacity(files.len());
+		for (i, file) in files.iter().enumerate() {
+			if let Err(e) = resolve_path(application_path, &file.path) {
+				session.emit(PatchEvent::FileFailed(file.path.clone(), e.to_string()));
+				prepared.push((i, DownloadResult { path: file.path.clone(), result: Err(e) }));
+				continue;
+			}
+			let expected = hash::strongest_hash(file);
+			if let Some(ref expected) = expected {
+				match first_by_hash.entry(expected.clone()) {
+					Entry::Occupied(first) => {
+						duplicates.insert(i, *first.get());
+						continue;
+					}
+					Entry::Vacant(first) => {
+						first.insert(i);
+					}
+				}
+			}
+			if let Some(stored) = store.as_ref().and_then(|store| stored_file(store, file)) {
+				session.emit(PatchEvent::FileVerified(file.path.clone(), stored.size));
+				prepared.push((i, DownloadResult { path: file.path.clone(), result: Ok(stored) }));
+				continue;
+			}
+			if let Some(expected) = expected {
+				if let Some((source, size)) = local.find(&expected) {
+					local_copies.push((i, source.to_path_buf(), size, expected));
+					continue;
+				}
+			}
+			jobs.push(DownloadJob {
+				file: file.clone(),
+				verify_checksum: !manifest_config.ignore_checksum,
+				allow_insecure: manifest_config.allow_insecure_patching,
+			});
+			job_positions.push(i);
+		}
+		if files.is_empty() && removed.is_empty() {
+			return Ok(None);
+		}
+
+		let local_bytes: u64 = local_copies.iter().map(|(_, _, size, _)| size).sum();
+		space::check_space(&temp_dir, space::required_space(&jobs, &downloader) + local_bytes)?;
+		for (i, source, _, expected) in local_copies {
+			let file = &files[i];
+			let result = dedup::stage_local(&source, file, &expected, &downloader.temp_path(file));
+			match result {
+				Ok(ref staged) => session.emit(PatchEvent::FileVerified(file.path.clone(), staged.size)),
+				Err(ref e) => session.emit(PatchEvent::FileFailed(file.path.clone(), e.to_string())),
+			}
+			prepared.push((i, DownloadResult { path: file.path.clone(), result }));
+		}
+		Ok(Some(PendingInstall {
+			request,
+			store,
+			downloader,
+			index,
+			prepared,
+			duplicates,
+			jobs,
+			job_positions,
+			shared: Vec::new(),
+		}))
+	}
+
+	/// Completes the install with the results of its download jobs, swapping every file into the application path if
+	/// all of them downloaded and verified.
+	fn finish(
+		self, downloads: Vec<DownloadResult>, session: &PatchSession,
+	) -> Result<Vec<DownloadResult>, FileManagerError> {
+		let PendingInstall { request, store, mut index, mut prepared, duplicates, job_positions, shared, .. } = self;
+		let InstallRequest { manifest_config, files, removed } = request;
+		let application_path = Path::new(&manifest_config.application_path);
+		let mut results: Vec<(usize, DownloadResult)> = job_positions.into_iter().zip(downloads).collect();
+		results.append(&mut prepared);
+		for i in shared {
+			let path = files[i].path.clone();
+			let result = match store.as_ref().and_then(|store| stored_file(store, &files[i])) {
+				Some(stored) => {
+					session.emit(PatchEvent::FileVerified(path.clone(), stored.size));
+					Ok(stored)
+				}
+				None => Err(FileManagerError::InstallAborted(path.clone())),
+			};
+			results.push((i, DownloadResult { path, result }));
+		}
+		results.sort_by_key(|(i, _)| *i);
+		let mut copies = Vec::with_capacity(duplicates.len());
+		for (&i, &first) in &duplicates {
+			let path = files[i].path.clone();
+			let first = results.binary_search_by_key(&first, |(j, _)| *j).map(|j| &results[j].1.result);
+			let result = match first {
+				Ok(Ok(downloaded)) => {
+					session.emit(PatchEvent::FileVerified(path.clone(), downloaded.size));
+					Ok(DownloadedFile { path: path.clone(), ..downloaded.clone() })
+				}
+				_ => Err(FileManagerError::InstallAborted(path.clone())),
+			};
+			copies.push((i, DownloadResult { path, result }));
+		}
+		results.append(&mut copies);
+		results.sort_by_key(|(i, _)| *i);
+		let mut results: Vec<DownloadResult> = results.into_iter().map(|(_, download)| download).collect();
+		let cancelled = session.is_cancelled();
+		if cancelled || results.iter().any(|download| download.result.is_err()) {
+			if let (true, Some(store)) = (cancelled, store.as_ref()) {
+				// Completed downloads are kept, so a later install need not download them again.
+				for downloaded in results.iter().filter_map(|download| download.result.as_ref().ok()) {
+					if downloaded.temp_path != store.blob_path(&downloaded.hash) {
+						let _ = store.adopt(&downloaded.hash, &downloaded.temp_path);
+					}
+				}
+			}
+			abort_staged(&mut results, None, session);
+			return Ok(results);
+		}
+
+		session.emit(PatchEvent::PhaseChanged(Phase::Installing));
+		let mut paths: Vec<String> = results.iter().map(|download| download.path.clone()).collect();
+		paths.extend(removed.iter().cloned());
+		let transaction = Transaction::begin(application_path, &paths)?;
+		let removal = removed.iter().try_for_each(|path| {
+			transaction.back_up(path)?;
+			index.remove(path);
+			Ok(())
+		});
+		if let Err(e) = removal {
+			// Rolling back restores the index from before the install.
+			transaction.abort()?;
+			abort_staged(&mut results, None, session);
+			return Err(e);
+		}
+		let mut failure = None;
+		for (i, download) in results.iter().enumerate() {
+			if let Ok(ref downloaded) = download.result {
+				let swapped = resolve_path(application_path, &downloaded.path).and_then(|dest| {
+					transaction.back_up(&downloaded.path)?;
+					match duplicates.get(&i) {
+						// The first file with the same contents has already been placed.
+						Some(&first) => {
+							let placed = resolve_path(application_path, &files[first].path)?;
+							dedup::place_duplicate(&downloaded.hash, &placed, &dest, store.as_ref())?;
+						}
+						None => place_file(downloaded, &dest, store.as_ref())?,
+					}
+					index.record(&downloaded.path, &dest, downloaded.hash.clone())
+				});
+				if let Err(e) = swapped {
+					failure = Some((i, e));
+					break;
+				}
+			}
+		}
+		match failure {
+			None => {
+				transaction.commit()?;
+				index.save(application_path)?;
+				session.emit(PatchEvent::PhaseChanged(Phase::Complete));
+			}
+			Some(failure) => {
+				// Rolling back restores the index from before the install.
+				transaction.abort()?;
+				abort_staged(&mut results, Some(failure), session);
+			}
+		}
+		if let Some(ref store) = store {
+			if let Err(e) = gc::record_references(store, application_path) {
+				log::warn!("Could not record the blobs used by {} - {}", application_path.display(), e);
+			}
+		}
+		Ok(results)
+	}
+}
+
+/// Gets the stored blob of `file` as a staged download, if the content store already holds it.
+fn stored_file(store: &ContentStore, file: &ManifestFile) -> Option<DownloadedFile> {
+	let hash = hash::strongest_hash(file)?;
+	let blob_path = store.blob_path(&hash);
+	let size = fs::metadata(&blob_path).ok().filter(|m| m.is_file())?.len();
+	Some(DownloadedFile {
+		path: file.path.clone(),
+		url: Url::from_file_path(&blob_path).map(|u| u.to_string()).unwrap_or_default(),
+		temp_path: blob_path,
+		size,
+		hash,
+	})
+}
+
+/// Discards staged downloads after an install is aborted, replacing their results with `InstallAborted` errors, or
+/// `Cancelled` errors if the session was cancelled.
+/// # Arguments
+/// * `results` - Download results of the install.
+/// * `failure` - Index and error of the file which caused the install to abort, if it was not a download failure.
+/// * `session` - Session the install belongs to, which is told of each file which failed.
+fn abort_staged(results: &mut [DownloadResult], mut failure: Option<(usize, FileManagerError)>, session: &PatchSession) {
+	for (i, download) in results.iter_mut().enumerate() {
+		if let Ok(ref downloaded) = download.result {
+			// Blobs reused from the content store are not staged copies.
+			if downloaded.temp_path.extension().is_some_and(|e| e == PARTIAL_EXTENSION) {
+				let _ = fs::remove_file(&downloaded.temp_path);
+			}
+			download.result = match failure {
+				Some((failed, _)) if failed == i => Err(failure.take().unwrap().1),
+				_ if session.is_cancelled() => Err(FileManagerError::Cancelled),
+				_ => Err(FileManagerError::InstallAborted(download.path.clone())),
+			};
+			if let Err(ref e) = download.result {
+				session.emit(PatchEvent::FileFailed(download.path.clone(), e.to_string()));
+			}
+		}
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::events::EventSink;
+	use crate::test_fixtures::{self, manifest_config};
+	use crate::test_server::{TestResponse, TestServer};
+
+	#[test]
+	fn should_install_files() {
+		let server = TestServer::start(|_| TestResponse::ok(b"abc"));
+		let dir = tempfile::tempdir().unwrap();
+		for &symlinked in &[true, false] {
+			let config = Config {
+				use_symlinked_storage: symlinked,
+				storage_path: Some(dir.path().join("store").to_string_lossy().into_owned()),
+				..Default::default()
+			};
+			let app = dir.path().join(format!("app-{}", symlinked));
+			let manifest_config = manifest_config(&server.url("/Manifest.toml"), &app);
+			let file = |path: &str| test_fixtures::file(path, &server.url("/abc"), b"abc");
+
+			let mirrors = Arc::new(MirrorTracker::new());
+			let installed = Path::new(&manifest_config.application_path).join("bin/app.exe");
+
+			// Installs with a rejected file install nothing.
+			let files = [file("bin/app.exe"), file("../escape.exe")];
+			let results = install_files(&config, &manifest_config, &files, &mirrors, &PatchSession::default()).unwrap();
+			match results[0].result {
+				Err(FileManagerError::InstallAborted(_)) => (),
+				ref other => panic!("Unexpected result: {:?}", other),
+			}
+			assert!(results[1].result.is_err());
+			assert!(!installed.exists());
+			assert!(!dir.path().join("escape.exe").exists());
+
+			let (events, received) = EventSink::channel();
+			let session = PatchSession::new(events);
+			let results = install_files(&config, &manifest_config, &[file("bin/app.exe")], &mirrors, &session).unwrap();
+			assert!(results[0].result.is_ok());
+			assert_eq!(fs::read(&installed).unwrap(), b"abc");
+			let received: Vec<PatchEvent> = received.try_iter().collect();
+			assert_eq!(received.first(), Some(&PatchEvent::PhaseChanged(Phase::Preparing)));
+			assert!(received.contains(&PatchEvent::FileStarted("bin/app.exe".to_owned(), server.url("/abc"))));
+			assert!(received.contains(&PatchEvent::FileVerified("bin/app.exe".to_owned(), 3)));
+			assert_eq!(received.last(), Some(&PatchEvent::PhaseChanged(Phase::Complete)));
+
+			if symlinked {
+				// Files already in the content store are linked without being downloaded.
+				let requests = server.requests().len();
+				let application_path = dir.path().join("app-other").to_string_lossy().into_owned();
+				let other = ManifestConfig { application_path, ..manifest_config.clone() };
+				let results = install_files(&config, &other, &[file("bin/app.exe")], &mirrors, &PatchSession::default()).unwrap();
+				assert!(results[0].result.is_ok());
+				assert_eq!(fs::read(dir.path().join("app-other/bin/app.exe")).unwrap(), b"abc");
+				assert_eq!(server.requests().len(), requests);
+			}
+		}
+	}
+
+	#[test]
+	fn should_dedupe_files() {
+		let server = TestServer::start(|_| TestResponse::ok(b"abc"));
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config { use_symlinked_storage: false, ..Default::default() };
+		let manifest_config = manifest_config(&server.url("/Manifest.toml"), dir.path());
+		let file = |path: &str| test_fixtures::file(path, &server.url(&format!("/{}", path)), b"abc");
+		let mirrors = Arc::new(MirrorTracker::new());
+		let session = PatchSession::default();
+
+		// Files with the same hash are downloaded once.
+		let files = [file("a/redist.exe"), file("b/redist.exe")];
+		let results = install_files(&config, &manifest_config, &files, &mirrors, &session).unwrap();
+		assert!(results.iter().all(|download| download.result.is_ok()));
+		assert_eq!(fs::read(dir.path().join("a/redist.exe")).unwrap(), b"abc");
+		assert_eq!(fs::read(dir.path().join("b/redist.exe")).unwrap(), b"abc");
+		assert_eq!(server.requests().len(), 1);
+
+		// Matching installed files are copied rather than downloaded.
+		let results = install_files(&config, &manifest_config, &[file("c/redist.exe")], &mirrors, &session).unwrap();
+		assert!(results[0].result.is_ok());
+		assert_eq!(fs::read(dir.path().join("c/redist.exe")).unwrap(), b"abc");
+		assert!(dir.path().join("a/redist.exe").exists());
+		assert_eq!(server.requests().len(), 1);
+	}
+
+	#[test]
+	fn should_reject_unsafe_paths() {
+		let app = Path::new("/games/app");
+
+		assert_eq!(resolve_path(app, "bin/app.exe").unwrap(), Path::new("/games/app/bin/app.exe"));
+		assert_eq!(resolve_path(app, "./app.exe").unwrap(), Path::new("/games/app/app.exe"));
+		for unsafe_path in &["../app.exe", "bin/../../app.exe", "/etc/passwd", "", ".", ".vanguard/tmp/x.part"] {
+			match resolve_path(app, unsafe_path) {
+				Err(FileManagerError::UnsafePath(_)) => (),
+				other => panic!("Unexpected result for {}: {:?}", unsafe_path, other),
+			}
+		}
+	}
+}
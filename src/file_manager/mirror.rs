@@ -0,0 +1,319 @@
+// --- Imports
+use super::{pool, FileManagerError};
+use crate::config;
+use crate::net::client::Client;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+// --- Consts
+/// Number of failures after which a mirror is deprioritized for the rest of the session.
+const MAX_MIRROR_FAILURES: u32 = 3;
+/// Name of the mirror statistics file, stored next to `vanguard.toml`.
+pub const MIRROR_STATS_FILE_NAME: &str = "mirrors.toml";
+/// Timeout for mirror probes, in milliseconds.
+const PROBE_TIMEOUT_MS: u64 = 5_000;
+/// Weight given to each new latency or throughput sample, so history adapts as network conditions change.
+const SAMPLE_WEIGHT: f64 = 0.3;
+/// Downloads smaller than this are dominated by latency, so are not used to measure throughput.
+const MIN_THROUGHPUT_SAMPLE: u64 = 64 * 1024;
+/// Throughput assumed for mirrors which have been probed but not yet downloaded from, in bytes per second.
+const DEFAULT_THROUGHPUT: f64 = 1024.0 * 1024.0;
+/// Transfer size mirrors are compared on, in bytes.
+const REFERENCE_SIZE: f64 = 1024.0 * 1024.0;
+/// Success and failure counts are halved once they reach this total, so old errors are eventually forgotten.
+const MAX_ATTEMPT_HISTORY: u64 = 100;
+
+/// Gets the mirror a URL belongs to, as `scheme://host:port`.
+/// Unparseable URLs are their own mirror.
+pub fn mirror_key(url: &str) -> String {
+	match Url::parse(url) {
+		Ok(parsed) => match (parsed.host_str(), parsed.port_or_known_default()) {
+			(Some(host), Some(port)) => format!("{}://{}:{}", parsed.scheme(), host, port),
+			(Some(host), None) => format!("{}://{}", parsed.scheme(), host),
+			_ => url.to_owned(),
+		},
+		Err(_) => url.to_owned(),
+	}
+}
+
+/// Gets the path of the mirror statistics file as a PathBuf.
+pub fn get_stats_path() -> Result<PathBuf, FileManagerError> {
+	Ok(config::get_app_dir()?.join(MIRROR_STATS_FILE_NAME))
+}
+
+/// Defines the measured performance of a single mirror.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MirrorRecord {
+	/// Moving average of the time taken to receive response headers, in milliseconds.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub latency_ms: Option<f64>,
+	/// Moving average of download throughput, in bytes per second.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub throughput: Option<f64>,
+	pub successes: u64,
+	pub failures: u64,
+}
+
+impl MirrorRecord {
+	/// Gets the fraction of recent attempts which failed.
+	pub fn error_rate(&self) -> f64 {
+		match self.successes + self.failures {
+			0 => 0.0,
+			total => self.failures as f64 / total as f64,
+		}
+	}
+
+	/// Gets the estimated time to download a reference sized file from this mirror, in seconds, accounting for
+	/// retries on other mirrors after errors. Lower is better. Returns None if the mirror has never been measured.
+	pub fn cost(&self) -> Option<f64> {
+		if self.latency_ms.is_none() && self.throughput.is_none() {
+			return None;
+		}
+		let latency = self.latency_ms.unwrap_or(0.0) / 1000.0;
+		let transfer = REFERENCE_SIZE / self.throughput.unwrap_or(DEFAULT_THROUGHPUT).max(1.0);
+		Some((latency + transfer) / (1.0 - self.error_rate().min(0.9)))
+	}
+
+	fn record_attempt(&mut self, success: bool) {
+		match success {
+			true => self.successes += 1,
+			false => self.failures += 1,
+		}
+		if self.successes + self.failures >= MAX_ATTEMPT_HISTORY {
+			self.successes /= 2;
+			self.failures /= 2;
+		}
+	}
+}
+
+/// Historical mirror performance, keyed by mirror (see `mirror_key`).
+/// Statistics saved after a session (see `get_stats_path`) let the next session rank mirrors from its first file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MirrorStats {
+	#[serde(rename = "mirror")]
+	pub mirrors: BTreeMap<String, MirrorRecord>,
+}
+
+impl MirrorStats {
+	/// Loads mirror statistics from `path`.
+	/// Statistics are only an optimisation, so a missing or unreadable file yields empty statistics.
+	pub fn load(path: &Path) -> MirrorStats {
+		fs::read_to_string(path).ok().and_then(|contents| toml::from_str(&contents).ok()).unwrap_or_default()
+	}
+
+	/// Saves mirror statistics to `path`, replacing any existing file.
+	pub fn save(&self, path: &Path) -> Result<(), FileManagerError> {
+		let contents = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		// Written via a temporary file, so an interrupted save can not leave truncated statistics.
+		let temp_path = path.with_extension("toml.tmp");
+		fs::write(&temp_path, contents)?;
+		fs::rename(&temp_path, path)?;
+		Ok(())
+	}
+
+	/// Gets the record of the mirror `url` belongs to, if it has been measured.
+	pub fn get(&self, url: &str) -> Option<&MirrorRecord> {
+		self.mirrors.get(&mirror_key(url))
+	}
+
+	fn record(&mut self, url: &str) -> &mut MirrorRecord {
+		self.mirrors.entry(mirror_key(url)).or_default()
+	}
+}
+
+/// Folds a new sample into a moving average.
+fn update_average(average: &mut Option<f64>, sample: f64) {
+	*average = Some(match *average {
+		Some(previous) => previous + SAMPLE_WEIGHT * (sample - previous),
+		None => sample,
+	});
+}
+
+/// Ranks mirrors for a patch session.
+/// Mirrors are ordered by their historical performance, and mirrors which repeatedly fail during the session are
+/// tried last.
+#[derive(Debug, Default)]
+pub struct MirrorTracker {
+	failures: Mutex<HashMap<String, u32>>,
+	stats: Mutex<MirrorStats>,
+}
+
+impl MirrorTracker {
+	/// Creates a tracker with no recorded failures or history.
+	pub fn new() -> MirrorTracker {
+		Default::default()
+	}
+
+	/// Creates a tracker which ranks mirrors using previously recorded statistics.
+	pub fn with_stats(stats: MirrorStats) -> MirrorTracker {
+		MirrorTracker { failures: Default::default(), stats: Mutex::new(stats) }
+	}
+
+	/// Gets a copy of the mirror statistics, including measurements from this session.
+	pub fn stats(&self) -> MirrorStats {
+		self.stats.lock().unwrap().clone()
+	}
+
+	/// Records a failed download from `url`.
+	pub fn record_failure(&self, url: &str) {
+		*self.failures.lock().unwrap().entry(mirror_key(url)).or_insert(0) += 1;
+		self.stats.lock().unwrap().record(url).record_attempt(false);
+	}
+
+	/// Records a successful download from `url`, clearing its session failures.
+	/// # Arguments
+	/// * `url` - The URL downloaded.
+	/// * `bytes` - Number of bytes downloaded.
+	/// * `elapsed` - Time taken by the whole download.
+	pub fn record_success(&self, url: &str, bytes: u64, elapsed: Duration) {
+		self.failures.lock().unwrap().remove(&mirror_key(url));
+		let mut stats = self.stats.lock().unwrap();
+		let record = stats.record(url);
+		record.record_attempt(true);
+		let seconds = elapsed.as_secs_f64();
+		if bytes >= MIN_THROUGHPUT_SAMPLE && seconds > 0.0 {
+			update_average(&mut record.throughput, bytes as f64 / seconds);
+		}
+	}
+
+	/// Records the time taken for `url` to respond.
+	pub fn record_latency(&self, url: &str, latency: Duration) {
+		let mut stats = self.stats.lock().unwrap();
+		update_average(&mut stats.record(url).latency_ms, latency.as_secs_f64() * 1000.0);
+	}
+
+	/// Measures the latency of every mirror in `urls` which has no recorded latency, up to `workers` at a time.
+	/// Each mirror is probed once, with a HEAD request to the first of its URLs. Unreachable mirrors and server
+	/// errors are recorded as failures.
+	pub fn probe(&self, client: &Client, urls: &[&String], workers: usize) {
+		let mut targets: BTreeMap<String, &String> = BTreeMap::new();
+		{
+			let stats = self.stats.lock().unwrap();
+			for url in urls {
+				if stats.get(url).is_none_or(|r| r.latency_ms.is_none()) {
+					targets.entry(mirror_key(url)).or_insert(url);
+				}
+			}
+		}
+		pool::parallel_map(targets.into_values().collect(), workers, |url| {
+			let start = Instant::now();
+			// Redirects are not followed, as their targets have not been checked against the secure patching policy.
+			// A redirect still shows the mirror is reachable.
+			let mut request = client.request("HEAD", url);
+			let response = request.timeout_connect(PROBE_TIMEOUT_MS).timeout_read(PROBE_TIMEOUT_MS).redirects(0).call();
+			match response.synthetic_error().is_none() && !response.server_error() {
+				true => self.record_latency(url, start.elapsed()),
+				false => self.record_failure(url),
+			}
+		});
+	}
+
+	/// Returns true if the mirror of `url` has failed too often to be preferred this session.
+	pub fn is_failing(&self, url: &str) -> bool {
+		self.failures.lock().unwrap().get(&mirror_key(url)).is_some_and(|f| *f >= MAX_MIRROR_FAILURES)
+	}
+
+	/// Orders `urls` for download, fastest measured mirror first. Mirrors which have never been measured follow,
+	/// in manifest order, and mirrors failing this session are moved last.
+	/// Failing mirrors are never dropped, as they may still be the only source of a file.
+	pub fn order<'a>(&self, urls: &'a [String]) -> Vec<&'a String> {
+		let (mut healthy, mut failing): (Vec<&String>, Vec<&String>) = urls.iter().partition(|url| !self.is_failing(url));
+		let stats = self.stats.lock().unwrap();
+		let cost = |url: &String| stats.get(url).and_then(MirrorRecord::cost).unwrap_or(f64::INFINITY);
+		healthy.sort_by(|a, b| cost(a).partial_cmp(&cost(b)).unwrap_or(Ordering::Equal));
+		failing.sort_by(|a, b| cost(a).partial_cmp(&cost(b)).unwrap_or(Ordering::Equal));
+		healthy.into_iter().chain(failing).collect()
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::test_server::{TestResponse, TestServer};
+
+	#[test]
+	fn should_deprioritize_failing_mirrors() {
+		let tracker = MirrorTracker::new();
+		let urls = vec![
+			"https://cdn.example.com/app.exe".to_owned(),
+			"https://mirror.example.org/app.exe".to_owned(),
+		];
+		for _ in 0..MAX_MIRROR_FAILURES {
+			tracker.record_failure("https://cdn.example.com:443/other.dll");
+		}
+
+		assert!(tracker.is_failing(&urls[0]));
+		assert_eq!(tracker.order(&urls), vec![&urls[1], &urls[0]]);
+		tracker.record_success(&urls[0], 3, Duration::from_millis(1));
+		assert_eq!(tracker.order(&urls), vec![&urls[0], &urls[1]]);
+	}
+
+	#[test]
+	fn should_rank_mirrors_by_history() {
+		let urls = vec![
+			"https://cdn.example.com/app.exe".to_owned(),
+			"https://probed.example.org/app.exe".to_owned(),
+			"https://unknown.example.net/app.exe".to_owned(),
+			"https://fast.example.org/app.exe".to_owned(),
+		];
+		let tracker = MirrorTracker::new();
+		tracker.record_latency(&urls[0], Duration::from_millis(300));
+		tracker.record_success(&urls[0], 1024 * 1024, Duration::from_secs(2));
+		tracker.record_latency(&urls[1], Duration::from_millis(100));
+		tracker.record_latency(&urls[3], Duration::from_millis(20));
+		tracker.record_success(&urls[3], 1024 * 1024, Duration::from_millis(100));
+
+		assert_eq!(tracker.order(&urls), vec![&urls[3], &urls[1], &urls[0], &urls[2]]);
+
+		// Unreliable mirrors are ranked down, even when fast.
+		for _ in 0..20 {
+			tracker.stats.lock().unwrap().record(&urls[3]).record_attempt(false);
+		}
+		assert_eq!(tracker.order(&urls)[0], &urls[1]);
+	}
+
+	#[test]
+	fn should_persist_stats() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join(MIRROR_STATS_FILE_NAME);
+		assert_eq!(MirrorStats::load(&path), MirrorStats::default());
+
+		let tracker = MirrorTracker::new();
+		tracker.record_latency("https://cdn.example.com/app.exe", Duration::from_millis(40));
+		tracker.record_failure("http://mirror.example.org/app.exe");
+		tracker.stats().save(&path).unwrap();
+		let loaded = MirrorStats::load(&path);
+
+		assert_eq!(loaded, tracker.stats());
+		assert_eq!(loaded.get("https://cdn.example.com:443/").unwrap().latency_ms, Some(40.0));
+		assert_eq!(loaded.get("http://mirror.example.org/").unwrap().failures, 1);
+	}
+
+	#[test]
+	fn should_probe_mirrors() {
+		let server = TestServer::start(|_| TestResponse::ok(b"abc"));
+		let other = TestServer::start(|_| TestResponse::status(500));
+		let tracker = MirrorTracker::new();
+		let (first, second, failing) = (server.url("/app.exe"), server.url("/other.dll"), other.url("/app.exe"));
+
+		tracker.probe(&Client::default(), &[&first, &second, &failing], 2);
+
+		// One probe per mirror
+		assert_eq!(server.requests().len(), 1);
+		let stats = tracker.stats();
+		assert!(stats.get(&first).unwrap().latency_ms.is_some());
+		assert_eq!(stats.get(&failing).unwrap().failures, 1);
+	}
+}
@@ -0,0 +1,152 @@
+// --- Modules
+pub mod bundle;
+pub mod dedup;
+pub mod download;
+pub mod gc;
+pub mod hash;
+pub mod index;
+pub mod install;
+pub mod mirror;
+pub mod partial;
+pub mod plan;
+pub mod pool;
+pub mod release;
+pub mod rollback;
+pub mod space;
+pub mod store;
+pub mod throttle;
+pub mod verify;
+
+// --- Imports
+use crate::config::ConfigError;
+use crate::manifest::ManifestError;
+use crate::net::NetError;
+use hash::FileHash;
+use std::error;
+use std::fmt;
+use std::io;
+
+// --- Consts
+/// Name of the directory within an application path used for Vanguard's own state.
+pub const STATE_DIR_NAME: &str = ".vanguard";
+
+/// Wrapper for file management errors.
+#[derive(Debug)]
+pub enum FileManagerError {
+	/// The patch session was cancelled before the file was installed.
+	Cancelled,
+	Config(ConfigError),
+	FileIO(io::Error),
+	/// A file's contents did not match its expected hash. Contains the file path, expected and actual hash.
+	HashMismatch(String, FileHash, FileHash),
+	/// A file was not installed because another file of the same install failed. Contains the file path.
+	InstallAborted(String),
+	/// There is not enough free space to install. Contains the path checked, required and available bytes.
+	InsufficientSpace(String, u64, u64),
+	/// A bundle is malformed or incomplete. Contains a description of the problem.
+	InvalidBundle(String),
+	/// Every mirror of a file failed. Contains the file path, and each mirror URL tried with its error.
+	MirrorsExhausted(String, Vec<(String, FileManagerError)>),
+	/// A blob was requested from the content store but has not been stored.
+	MissingBlob(FileHash),
+	Manifest(ManifestError),
+	/// A request to a mirror failed.
+	Net(NetError),
+	/// A file has no URLs to download from. Contains the file path.
+	NoMirrors(String),
+	/// A file has no https URLs while secure patching is enforced. Contains the file path.
+	NoSecureMirrors(String),
+	/// A file's size did not match its expected size. Contains the file path, expected and actual size.
+	SizeMismatch(String, u64, u64),
+	/// A local source of a file could not be read. Contains the source URL and error description.
+	SourceUnavailable(String, String),
+	/// No configured manifest has the given URL or label. Contains the URL or label.
+	UnknownManifest(String),
+	/// A manifest file path would escape the application path.
+	UnsafePath(String),
+	/// A file has no valid hash, so its contents can not be verified. Contains the file path.
+	UnverifiableFile(String),
+}
+impl fmt::Display for FileManagerError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			FileManagerError::Cancelled => write!(f, "Patch cancelled"),
+			FileManagerError::Config(ref e) => e.fmt(f),
+			FileManagerError::FileIO(ref e) => e.fmt(f),
+			FileManagerError::HashMismatch(ref path, ref expected, ref actual) => {
+				write!(f, "Hash mismatch for {} - expected {}, got {}", path, expected, actual)
+			}
+			FileManagerError::InstallAborted(ref path) => {
+				write!(f, "Install of {} aborted as another file failed to install", path)
+			}
+			FileManagerError::InsufficientSpace(ref path, required, available) => write!(
+				f,
+				"Not enough disk space at {} - {} bytes required, {} bytes available",
+				path, required, available
+			),
+			FileManagerError::InvalidBundle(ref desc) => write!(f, "Invalid bundle: {}", desc),
+			FileManagerError::MirrorsExhausted(ref path, ref failures) => match failures.last() {
+				Some((_, e)) => write!(f, "All {} mirrors failed for {} - last error: {}", failures.len(), path, e),
+				None => write!(f, "All mirrors failed for {}", path),
+			},
+			FileManagerError::MissingBlob(ref hash) => write!(f, "Blob not found in content store: {}", hash),
+			FileManagerError::Manifest(ref e) => e.fmt(f),
+			FileManagerError::Net(ref e) => e.fmt(f),
+			FileManagerError::NoMirrors(ref path) => write!(f, "No usable mirrors for {}", path),
+			FileManagerError::NoSecureMirrors(ref path) => {
+				write!(f, "No https mirrors for {} and secure patching is enabled", path)
+			}
+			FileManagerError::SizeMismatch(ref path, expected, actual) => {
+				write!(f, "Size mismatch for {} - expected {} bytes, got {}", path, expected, actual)
+			}
+			FileManagerError::SourceUnavailable(ref url, ref desc) => write!(f, "Could not read {} - {}", url, desc),
+			FileManagerError::UnknownManifest(ref key) => write!(f, "No configured manifest matches {}", key),
+			FileManagerError::UnsafePath(ref path) => write!(f, "Unsafe file path: {}", path),
+			FileManagerError::UnverifiableFile(ref path) => write!(f, "No valid hash to verify {}", path),
+		}
+	}
+}
+impl error::Error for FileManagerError {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match *self {
+			FileManagerError::Cancelled => None,
+			FileManagerError::Config(ref e) => Some(e),
+			FileManagerError::FileIO(ref e) => Some(e),
+			FileManagerError::HashMismatch(_, _, _) => None,
+			FileManagerError::InstallAborted(_) => None,
+			FileManagerError::InsufficientSpace(_, _, _) => None,
+			FileManagerError::InvalidBundle(_) => None,
+			FileManagerError::MirrorsExhausted(_, ref failures) => failures.last().map(|(_, e)| e as &dyn error::Error),
+			FileManagerError::MissingBlob(_) => None,
+			FileManagerError::Manifest(ref e) => Some(e),
+			FileManagerError::Net(ref e) => Some(e),
+			FileManagerError::NoMirrors(_) => None,
+			FileManagerError::NoSecureMirrors(_) => None,
+			FileManagerError::SizeMismatch(_, _, _) => None,
+			FileManagerError::SourceUnavailable(_, _) => None,
+			FileManagerError::UnknownManifest(_) => None,
+			FileManagerError::UnsafePath(_) => None,
+			FileManagerError::UnverifiableFile(_) => None,
+		}
+	}
+}
+impl From<ConfigError> for FileManagerError {
+	fn from(item: ConfigError) -> FileManagerError {
+		FileManagerError::Config(item)
+	}
+}
+impl From<ManifestError> for FileManagerError {
+	fn from(item: ManifestError) -> FileManagerError {
+		FileManagerError::Manifest(item)
+	}
+}
+impl From<NetError> for FileManagerError {
+	fn from(item: NetError) -> FileManagerError {
+		FileManagerError::Net(item)
+	}
+}
+impl From<io::Error> for FileManagerError {
+	fn from(item: io::Error) -> FileManagerError {
+		FileManagerError::FileIO(item)
+	}
+}
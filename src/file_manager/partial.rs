@@ -0,0 +1,121 @@
+// --- Imports
+use super::hash::FileHash;
+use super::FileManagerError;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// --- Consts
+/// Extension of the state files kept beside partial downloads.
+pub const STATE_EXTENSION: &str = "state";
+
+/// Defines the progress of a partial download, stored beside the partial file so that the download can be
+/// resumed, including after the process restarts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartialState {
+	/// URL the partial file was downloaded from.
+	pub url: String,
+	/// Number of bytes of the partial file which have been written.
+	pub bytes: u64,
+	/// ETag of the response, used to check that the remote file has not changed before resuming.
+	pub etag: Option<String>,
+	/// Last-Modified date of the response, used when the server does not provide an ETag.
+	pub last_modified: Option<String>,
+	/// Expected hash of the complete file, if the manifest provides one.
+	pub expected: Option<FileHash>,
+}
+
+impl PartialState {
+	/// Gets the path of the state file for the partial download at `partial`.
+	pub fn path(partial: &Path) -> PathBuf {
+		partial.with_extension(STATE_EXTENSION)
+	}
+
+	/// Loads the state of the partial download at `partial`.
+	/// Returns None if there is no state, it is unreadable, or the partial file is shorter than the state records.
+	pub fn load(partial: &Path) -> Option<PartialState> {
+		let state: PartialState = toml::from_str(&fs::read_to_string(PartialState::path(partial)).ok()?).ok()?;
+		match fs::metadata(partial) {
+			Ok(metadata) if metadata.len() >= state.bytes => Some(state),
+			_ => None,
+		}
+	}
+
+	/// Saves this state for the partial download at `partial`.
+	/// The state is written to a temporary file first, so an interrupted save can not leave a truncated state.
+	pub fn save(&self, partial: &Path) -> Result<(), FileManagerError> {
+		let contents = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		let path = PartialState::path(partial);
+		let temp_path = path.with_extension(format!("{}.tmp", STATE_EXTENSION));
+		fs::write(&temp_path, contents)?;
+		fs::rename(&temp_path, &path)?;
+		Ok(())
+	}
+
+	/// Removes the state of the partial download at `partial`, if present.
+	pub fn remove(partial: &Path) {
+		let _ = fs::remove_file(PartialState::path(partial));
+	}
+
+	/// Removes the partial download at `partial` and its state.
+	pub fn discard(partial: &Path) {
+		let _ = fs::remove_file(partial);
+		PartialState::remove(partial);
+	}
+
+	/// Returns true if this partial download can be resumed from `url` for a file expected to hash to `expected`.
+	/// Files with a known hash may be resumed from any mirror, as the final hash is verified. Files without one may
+	/// only be resumed from the URL they were started from.
+	pub fn can_resume(&self, url: &str, expected: Option<&FileHash>) -> bool {
+		self.bytes > 0 && self.expected.as_ref() == expected && (expected.is_some() || self.url == url)
+	}
+
+	/// Gets the `If-Range` validator to send when resuming from `url`, if any.
+	/// Validators are specific to a server, so are only sent to the URL the download was started from.
+	pub fn validator(&self, url: &str) -> Option<&str> {
+		match self.url == url {
+			true => self.etag.as_deref().or(self.last_modified.as_deref()),
+			false => None,
+		}
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::file_manager::hash::HashAlgorithm;
+
+	#[test]
+	fn should_persist_partial_state() {
+		let dir = tempfile::tempdir().unwrap();
+		let partial = dir.path().join("file.part");
+		let expected = FileHash::new(HashAlgorithm::Sha1, "1f8ac10f23c5b5bc1167bda84b833e5c057a77d2");
+		let state = PartialState {
+			url: "https://cdn.example.com/app.exe".to_owned(),
+			bytes: 3,
+			etag: Some("\"v1\"".to_owned()),
+			last_modified: None,
+			expected: expected.clone(),
+		};
+		state.save(&partial).unwrap();
+
+		// State without a matching partial file is ignored.
+		assert_eq!(PartialState::load(&partial), None);
+		fs::write(&partial, b"abc").unwrap();
+		let loaded = PartialState::load(&partial).unwrap();
+		assert_eq!(loaded, state);
+
+		assert!(loaded.can_resume("https://mirror.example.org/app.exe", expected.as_ref()));
+		assert!(!loaded.can_resume("https://cdn.example.com/app.exe", None));
+		assert_eq!(loaded.validator("https://cdn.example.com/app.exe"), Some("\"v1\""));
+		assert_eq!(loaded.validator("https://mirror.example.org/app.exe"), None);
+
+		PartialState::discard(&partial);
+		assert!(!partial.exists());
+		assert!(!PartialState::path(&partial).exists());
+	}
+}
@@ -0,0 +1,338 @@
+// --- Imports
+use super::dedup::LocalFiles;
+use super::download::DownloadResult;
+use super::hash;
+use super::index::FileIndex;
+use super::install::{self, resolve_path, InstallRequest};
+use super::mirror::MirrorTracker;
+use super::release::{self, InstalledRelease};
+use super::store::ContentStore;
+use super::verify::{self, FileStatus};
+use super::FileManagerError;
+use crate::config::{Config, ManifestConfig};
+use crate::events::EventSink;
+use crate::manifest::cache::ManifestCache;
+use crate::manifest::fetch::{self, ManifestSource};
+use crate::manifest::manifest_spec::{Manifest, ManifestFile};
+use crate::net::client::Client;
+use crate::session::PatchSession;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Defines how much of an install an update replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PlanKind {
+	/// No installed file can be kept, ie for a first install.
+	Full,
+	/// Only files which differ from the installed files are changed.
+	Delta,
+}
+
+/// Defines the changes needed to bring an application path up to date with a manifest.
+/// Plans are serializable, so a launcher can show them before the user commits, and later apply them as-is with
+/// `apply_plan`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdatePlan {
+	/// Configured URL of the manifest.
+	pub manifest_url: String,
+	/// Label of the manifest.
+	pub label: String,
+	/// Application version described by the manifest.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub app_version: Option<String>,
+	/// Build number of the application described by the manifest.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub build: Option<u64>,
+	/// Where the manifest came from. Plans made from a cached manifest while offline may be out of date.
+	pub source: ManifestSource,
+	pub kind: PlanKind,
+	/// Manifest paths of installed files which are no longer in the manifest, and will be removed.
+	pub delete: Vec<String>,
+	/// Total size of the files to fetch and replace, in bytes. Files of unknown size are not counted.
+	pub total_bytes: u64,
+	/// Bytes which must be downloaded. Files already in the content store or matching another installed file, and
+	/// files with the same contents as an earlier file, are not counted.
+	pub download_bytes: u64,
+	/// Files which are not installed.
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub fetch: Vec<ManifestFile>,
+	/// Installed files whose contents differ from the manifest, or could not be read.
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub replace: Vec<ManifestFile>,
+}
+
+impl UpdatePlan {
+	/// Returns true if the application path is already up to date.
+	pub fn is_empty(&self) -> bool {
+		self.fetch.is_empty() && self.replace.is_empty() && self.delete.is_empty()
+	}
+
+	/// Gets the number of files which will be installed.
+	pub fn file_count(&self) -> usize {
+		self.fetch.len() + self.replace.len()
+	}
+
+	/// Iterates over every file which will be installed.
+	pub fn files_to_install(&self) -> impl Iterator<Item = &ManifestFile> {
+		self.fetch.iter().chain(self.replace.iter())
+	}
+}
+
+/// Fetches the manifest configured by `manifest_config` and plans the update of its application path, without
+/// downloading any files.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest to update.
+/// * `cache` - Cache of previously fetched manifests.
+/// * `events` - Sink which is told of retries, and when the manifest has been fetched.
+pub fn plan_update(
+	config: &Config, manifest_config: &ManifestConfig, cache: &ManifestCache, events: &EventSink,
+) -> Result<UpdatePlan, FileManagerError> {
+	let client = Client::for_manifest(config, manifest_config)?;
+	let fetched = fetch::fetch_manifest(&client, config, manifest_config, cache, events)?;
+	plan_manifest(config, manifest_config, &fetched.manifest, fetched.source)
+}
+
+/// Plans the update of the application path of `manifest_config` to `manifest`.
+/// Installed files are compared as by `verify::verify_files`, so files unchanged since they were last verified are
+/// not hashed again. Files recorded in the file index which are not in `manifest` are planned for removal.
+/// Fails with `ManifestError::Downgrade` if `manifest` is older than the installed release, unless the manifest
+/// config allows downgrades, and with `FileManagerError::UnsafePath` if any file path would escape the application
+/// path, as the manifest could never be fully installed.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest.
+/// * `manifest` - The manifest to update to.
+/// * `source` - Where the manifest came from.
+pub fn plan_manifest(
+	config: &Config, manifest_config: &ManifestConfig, manifest: &Manifest, source: ManifestSource,
+) -> Result<UpdatePlan, FileManagerError> {
+	let application_path = Path::new(&manifest_config.application_path);
+	release::check_downgrade(application_path, manifest, manifest_config.allow_downgrade)?;
+	for file in &manifest.files {
+		resolve_path(application_path, &file.path)?;
+	}
+	let reports = verify::verify_files(config, manifest_config, &manifest.files, false)?;
+	let statuses: HashMap<&str, &FileStatus> = reports.iter().map(|r| (r.path.as_str(), &r.status)).collect();
+	let mut fetch = Vec::new();
+	let mut replace = Vec::new();
+	for file in &manifest.files {
+		match statuses.get(file.path.as_str()) {
+			Some(FileStatus::Missing) => fetch.push(file.clone()),
+			Some(FileStatus::Ok) | Some(FileStatus::Extra) | None => (),
+			Some(_) => replace.push(file.clone()),
+		}
+	}
+
+	let index = FileIndex::load(application_path);
+	let listed: HashSet<&str> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+	let delete = index
+		.entries
+		.keys()
+		.filter(|path| !listed.contains(path.as_str()))
+		.filter(|path| resolve_path(application_path, path).is_ok_and(|dest| fs::symlink_metadata(dest).is_ok()))
+		.cloned()
+		.collect();
+
+	let store = match config.use_symlinked_storage {
+		true => Some(ContentStore::from_config(config)?),
+		false => None,
+	};
+	let local = LocalFiles::new(application_path, &index);
+	let mut seen = HashSet::new();
+	let (mut total_bytes, mut download_bytes) = (0, 0);
+	for file in fetch.iter().chain(replace.iter()) {
+		let size = file.size.unwrap_or(0);
+		total_bytes += size;
+		let reused = hash::strongest_hash(file).is_some_and(|expected| {
+			let stored = store.as_ref().is_some_and(|store| store.contains(&expected));
+			stored || local.find(&expected).is_some() || !seen.insert(expected)
+		});
+		if !reused {
+			download_bytes += size;
+		}
+	}
+
+	let installed = reports.iter().any(|r| r.status == FileStatus::Ok);
+	Ok(UpdatePlan {
+		manifest_url: manifest_config.url.clone(),
+		label: manifest.label.clone(),
+		app_version: manifest.app_version.clone(),
+		build: manifest.build,
+		source,
+		kind: match installed || manifest.files.is_empty() {
+			true => PlanKind::Delta,
+			false => PlanKind::Full,
+		},
+		delete,
+		total_bytes,
+		download_bytes,
+		fetch,
+		replace,
+	})
+}
+
+/// Applies `plan` to the application path of `manifest_config`, installing its files and removing its deleted files
+/// in a single install. See `install::install_changes`.
+/// Returns one result per file installed, fetched files first. Once every file is installed, the release of the plan is
+/// recorded as the installed release.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest the plan was made for.
+/// * `plan` - The plan to apply.
+/// * `mirrors` - Mirror tracker used to rank the files' mirrors.
+/// * `session` - Session the install belongs to.
+pub fn apply_plan(
+	config: &Config, manifest_config: &ManifestConfig, plan: &UpdatePlan, mirrors: &Arc<MirrorTracker>,
+	session: &PatchSession,
+) -> Result<Vec<DownloadResult>, FileManagerError> {
+	apply_plans(config, &[(manifest_config, plan)], mirrors, session).remove(0)
+}
+
+/// Applies several plans as `apply_plan` does, downloading the files of every plan on one pool of up to
+/// `Config.maximum_parallel_files` workers. See `install::install_all`.
+/// Returns one result per plan, in the order given. A plan which fails to install does not stop the others.
+/// # Arguments
+/// * `config` - Application config.
+/// * `plans` - The plans to apply, with the configs of the manifests they were made for.
+/// * `mirrors` - Mirror tracker used to rank the files' mirrors.
+/// * `session` - Session the installs belong to.
+pub fn apply_plans(
+	config: &Config, plans: &[(&ManifestConfig, &UpdatePlan)], mirrors: &Arc<MirrorTracker>, session: &PatchSession,
+) -> Vec<Result<Vec<DownloadResult>, FileManagerError>> {
+	let files: Vec<Vec<ManifestFile>> =
+		plans.iter().map(|(_, plan)| plan.files_to_install().cloned().collect()).collect();
+	let requests: Vec<InstallRequest> = plans
+		.iter()
+		.zip(&files)
+		.map(|(&(manifest_config, plan), files)| InstallRequest { manifest_config, files, removed: &plan.delete })
+		.collect();
+	let installed = install::install_all(config, &requests, mirrors, session);
+	plans
+		.iter()
+		.zip(installed)
+		.map(|(&(manifest_config, plan), results)| {
+			let results = results?;
+			if results.iter().all(|download| download.result.is_ok()) {
+				let application_path = &manifest_config.application_path;
+				let installed = InstalledRelease::new(&plan.label, plan.app_version.as_deref(), plan.build);
+				if let Err(e) = installed.save(Path::new(application_path)) {
+					log::warn!("Could not record the installed release of {} - {}", application_path, e);
+				}
+			}
+			Ok(results)
+		})
+		.collect()
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::file_manager::hash::{FileHash, HashAlgorithm};
+	use crate::file_manager::rollback;
+	use crate::manifest::ManifestError;
+	use crate::test_fixtures::{self, manifest_config};
+	use crate::test_server::{TestResponse, TestServer};
+
+	fn manifest(files: Vec<ManifestFile>) -> Manifest {
+		Manifest { app_version: Some("1.1.0".to_owned()), ..test_fixtures::manifest(files) }
+	}
+
+	#[test]
+	fn should_plan_and_apply_updates() {
+		let server = TestServer::start(|request| match request.path.as_str() {
+			"/abc" => TestResponse::ok(b"abc"),
+			_ => TestResponse::ok(b"xyz"),
+		});
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config { use_symlinked_storage: false, ..Default::default() };
+		let manifest_config = manifest_config(&server.url("/Manifest.toml"), dir.path());
+		let file = |path: &str, contents: &str| {
+			test_fixtures::file(path, &server.url(&format!("/{}", contents)), contents.as_bytes())
+		};
+		let files = vec![file("app.exe", "abc"), file("data.pak", "xyz"), file("copy.exe", "abc")];
+		let target = manifest(files);
+
+		let plan = plan_manifest(&config, &manifest_config, &target, ManifestSource::Downloaded).unwrap();
+		assert_eq!(plan.kind, PlanKind::Full);
+		assert_eq!((plan.file_count(), plan.total_bytes, plan.download_bytes), (3, 9, 6));
+
+		// An older release left data.pak damaged, and installed a file the manifest no longer lists.
+		fs::write(dir.path().join("app.exe"), b"abc").unwrap();
+		fs::write(dir.path().join("data.pak"), b"old").unwrap();
+		fs::write(dir.path().join("old.dll"), b"old").unwrap();
+		let mut index = FileIndex::load(dir.path());
+		let old_hash = FileHash::new(HashAlgorithm::Sha1, "356a192b7913b04c54574d18c28d46e6395428ab").unwrap();
+		index.record("old.dll", &dir.path().join("old.dll"), old_hash).unwrap();
+		index.save(dir.path()).unwrap();
+		InstalledRelease::new("App", Some("1.0.0"), None).save(dir.path()).unwrap();
+
+		let plan = plan_manifest(&config, &manifest_config, &target, ManifestSource::Downloaded).unwrap();
+		assert_eq!(plan.kind, PlanKind::Delta);
+		assert_eq!(plan.fetch.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["copy.exe"]);
+		assert_eq!(plan.replace.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["data.pak"]);
+		assert_eq!(plan.delete, vec!["old.dll"]);
+		assert_eq!((plan.total_bytes, plan.download_bytes), (6, 3));
+
+		// Plans are applied as-is after a round trip through their serialized form.
+		let plan: UpdatePlan = toml::from_str(&toml::to_string(&plan).unwrap()).unwrap();
+		let mirrors = Arc::new(MirrorTracker::new());
+		let results = apply_plan(&config, &manifest_config, &plan, &mirrors, &PatchSession::default()).unwrap();
+		assert!(results.iter().all(|download| download.result.is_ok()));
+		assert_eq!(fs::read(dir.path().join("data.pak")).unwrap(), b"xyz");
+		assert_eq!(fs::read(dir.path().join("copy.exe")).unwrap(), b"abc");
+		assert!(!dir.path().join("old.dll").exists());
+		let installed_version = || InstalledRelease::load(dir.path()).and_then(|installed| installed.app_version);
+		assert_eq!(installed_version().as_deref(), Some("1.1.0"));
+		assert_eq!(server.hits("/xyz"), 1);
+		assert_eq!(server.hits("/abc"), 0);
+		assert!(plan_manifest(&config, &manifest_config, &target, ManifestSource::Downloaded).unwrap().is_empty());
+
+		// Removed files, the index and the recorded release are restored when the update is rolled back.
+		rollback::rollback(dir.path()).unwrap();
+		assert_eq!(fs::read(dir.path().join("old.dll")).unwrap(), b"old");
+		assert_eq!(fs::read(dir.path().join("data.pak")).unwrap(), b"old");
+		assert_eq!(installed_version().as_deref(), Some("1.0.0"));
+		let plan = plan_manifest(&config, &manifest_config, &target, ManifestSource::Downloaded).unwrap();
+		assert_eq!(plan.delete, vec!["old.dll"]);
+
+		// Manifests with files outside of the application path are refused rather than partially planned.
+		let unsafe_target = manifest(vec![file("app.exe", "abc"), file("../escape.exe", "abc")]);
+		match plan_manifest(&config, &manifest_config, &unsafe_target, ManifestSource::Downloaded) {
+			Err(FileManagerError::UnsafePath(ref path)) => assert_eq!(path, "../escape.exe"),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn should_refuse_downgrades() {
+		let server = TestServer::start(|_| TestResponse::ok(b"abc"));
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config { use_symlinked_storage: false, ..Default::default() };
+		let mut manifest_config = manifest_config(&server.url("/Manifest.toml"), dir.path());
+		let release = |build| Manifest {
+			build: Some(build),
+			..manifest(vec![test_fixtures::file("app.exe", &server.url("/abc"), b"abc")])
+		};
+
+		let plan = plan_manifest(&config, &manifest_config, &release(2), ManifestSource::Downloaded).unwrap();
+		let mirrors = Arc::new(MirrorTracker::new());
+		apply_plan(&config, &manifest_config, &plan, &mirrors, &PatchSession::default()).unwrap();
+		assert_eq!(InstalledRelease::load(dir.path()).and_then(|installed| installed.build), Some(2));
+
+		match plan_manifest(&config, &manifest_config, &release(1), ManifestSource::Downloaded) {
+			Err(FileManagerError::Manifest(ManifestError::Downgrade(ref from, ref to))) => {
+				assert_eq!((from.as_str(), to.as_str()), ("1.1.0 (build 2)", "1.1.0 (build 1)"))
+			}
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		manifest_config.allow_downgrade = true;
+		assert!(plan_manifest(&config, &manifest_config, &release(1), ManifestSource::Downloaded).unwrap().is_empty());
+	}
+}
@@ -0,0 +1,36 @@
+// --- Imports
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+
+/// Applies `f` to every item on a pool of up to `workers` threads, returning the results in the order of `items`.
+/// # Arguments
+/// * `items` - The items to process.
+/// * `workers` - Maximum number of threads to use. At least one thread is used.
+/// * `f` - Function applied to each item.
+pub fn parallel_map<T, R, F>(items: Vec<T>, workers: usize, f: F) -> Vec<R>
+where
+	T: Send,
+	R: Send,
+	F: Fn(T) -> R + Sync,
+{
+	let item_count = items.len();
+	let queue = Mutex::new(items.into_iter().enumerate().collect::<VecDeque<_>>());
+	let results = Mutex::new(Vec::with_capacity(item_count));
+	thread::scope(|scope| {
+		for _ in 0..workers.max(1).min(item_count) {
+			scope.spawn(|| loop {
+				let next = queue.lock().unwrap().pop_front();
+				let (index, item) = match next {
+					Some(next) => next,
+					None => break,
+				};
+				let result = f(item);
+				results.lock().unwrap().push((index, result));
+			});
+		}
+	});
+	let mut results = results.into_inner().unwrap();
+	results.sort_by_key(|(index, _)| *index);
+	results.into_iter().map(|(_, result)| result).collect()
+}
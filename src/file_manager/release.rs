@@ -0,0 +1,78 @@
+// --- Imports
+use super::{FileManagerError, STATE_DIR_NAME};
+use crate::manifest::{self, manifest_spec::Manifest};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// --- Consts
+/// Name of the record of the installed release, within the state directory of an application path.
+pub const RELEASE_FILE_NAME: &str = "release.toml";
+
+/// Defines the release last installed to an application path, so later updates can refuse to downgrade it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstalledRelease {
+	/// Label of the installed manifest.
+	pub label: String,
+	/// Application version of the installed manifest.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub app_version: Option<String>,
+	/// Build number of the installed manifest.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub build: Option<u64>,
+}
+
+impl InstalledRelease {
+	/// Creates the record of an install of `manifest`.
+	pub fn new(label: &str, app_version: Option<&str>, build: Option<u64>) -> InstalledRelease {
+		InstalledRelease { label: label.to_owned(), app_version: app_version.map(String::from), build }
+	}
+
+	/// Gets the path of the release record for an application path.
+	pub fn path(application_path: &Path) -> PathBuf {
+		application_path.join(STATE_DIR_NAME).join(RELEASE_FILE_NAME)
+	}
+
+	/// Loads the release installed to an application path.
+	/// Returns None if no install has been recorded, or the record can not be read.
+	pub fn load(application_path: &Path) -> Option<InstalledRelease> {
+		fs::read_to_string(InstalledRelease::path(application_path))
+			.ok()
+			.and_then(|contents| toml::from_str(&contents).ok())
+	}
+
+	/// Saves the release record to an application path, replacing any existing record.
+	pub fn save(&self, application_path: &Path) -> Result<(), FileManagerError> {
+		let path = InstalledRelease::path(application_path);
+		fs::create_dir_all(path.parent().unwrap_or(application_path))?;
+		let contents = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		// Written via a temporary file, so an interrupted save can not leave a truncated record.
+		let temp_path = path.with_extension("toml.tmp");
+		fs::write(&temp_path, contents)?;
+		fs::rename(&temp_path, &path)?;
+		Ok(())
+	}
+}
+
+/// Checks that installing `target` to an application path does not downgrade the release installed there.
+/// See `manifest::check_downgrade`. Application paths without a recorded release are always allowed.
+/// # Arguments
+/// * `application_path` - The application path.
+/// * `target` - The manifest to be installed.
+/// * `allow_downgrade` - If true, downgrades are permitted.
+pub fn check_downgrade(
+	application_path: &Path, target: &Manifest, allow_downgrade: bool,
+) -> Result<(), FileManagerError> {
+	if let Some(release) = InstalledRelease::load(application_path) {
+		let installed = Manifest {
+			label: release.label,
+			app_version: release.app_version,
+			build: release.build,
+			..Default::default()
+		};
+		manifest::check_downgrade(&installed, target, allow_downgrade)?;
+	}
+	Ok(())
+}
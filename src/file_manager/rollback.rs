@@ -0,0 +1,278 @@
+// --- Imports
+use super::index::INDEX_FILE_NAME;
+use super::install::resolve_path;
+use super::release::RELEASE_FILE_NAME;
+use super::{FileManagerError, STATE_DIR_NAME};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// --- Consts
+/// Name of the install journal, within the state directory of an application path.
+pub const JOURNAL_FILE_NAME: &str = "rollback.toml";
+/// Name of the directory holding files replaced by the last install, within the state directory.
+pub const ROLLBACK_DIR_NAME: &str = "rollback";
+/// Names of the state files describing the installed files, which are restored when an install is rolled back.
+const STATE_FILE_NAMES: [&str; 2] = [INDEX_FILE_NAME, RELEASE_FILE_NAME];
+
+/// Defines a file written by an install.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+	/// Manifest path of the file.
+	pub path: String,
+	/// True if a previous version of the file existed, and is (or is about to be) kept in the rollback directory.
+	pub backed_up: bool,
+}
+
+/// Journal of the last install into an application path, used to roll it back.
+/// The journal is written before any file is replaced, so an install interrupted part way (ie, by a crash or power
+/// loss) can be rolled back the next time the application path is patched.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InstallJournal {
+	/// True once every file has been swapped into the application path.
+	pub complete: bool,
+	/// True if the file index and release record were kept when the install began, so they are restored with the
+	/// files. Journals written before they were kept restore only the files.
+	#[serde(default)]
+	pub state_saved: bool,
+	#[serde(rename = "file")]
+	pub files: Vec<JournalEntry>,
+}
+
+impl InstallJournal {
+	/// Gets the path of the journal for an application path.
+	pub fn path(application_path: &Path) -> PathBuf {
+		application_path.join(STATE_DIR_NAME).join(JOURNAL_FILE_NAME)
+	}
+
+	/// Loads the journal of an application path, if there is one.
+	pub fn load(application_path: &Path) -> Result<Option<InstallJournal>, FileManagerError> {
+		match fs::read_to_string(InstallJournal::path(application_path)) {
+			Ok(contents) => toml::from_str(&contents)
+				.map(Some)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into()),
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Saves the journal of an application path.
+	/// The journal is written via a temporary file, so an interrupted save can not leave a truncated journal.
+	pub fn save(&self, application_path: &Path) -> Result<(), FileManagerError> {
+		let path = InstallJournal::path(application_path);
+		fs::create_dir_all(path.parent().unwrap_or(application_path))?;
+		let contents = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		let temp_path = path.with_extension("toml.tmp");
+		fs::write(&temp_path, contents)?;
+		fs::rename(&temp_path, &path)?;
+		Ok(())
+	}
+}
+
+/// Gets the rollback directory of an application path.
+pub fn get_rollback_dir(application_path: &Path) -> PathBuf {
+	application_path.join(STATE_DIR_NAME).join(ROLLBACK_DIR_NAME)
+}
+
+/// An install in progress. Files replaced through a transaction are kept until the next install, so the install can
+/// be rolled back even after it has been committed.
+#[derive(Debug)]
+pub struct Transaction {
+	application_path: PathBuf,
+	journal: InstallJournal,
+}
+
+impl Transaction {
+	/// Begins an install of `paths` into an application path.
+	/// An interrupted previous install is rolled back first, and the files kept from a completed previous install
+	/// are discarded. The file index and release record are kept, so rolling back also restores them.
+	/// # Arguments
+	/// * `application_path` - The application path.
+	/// * `paths` - Manifest paths of every file the install will write. Paths must already be validated.
+	pub fn begin(application_path: &Path, paths: &[String]) -> Result<Transaction, FileManagerError> {
+		recover(application_path)?;
+		discard(application_path)?;
+		let mut files = Vec::with_capacity(paths.len());
+		for path in paths {
+			let dest = resolve_path(application_path, path)?;
+			files.push(JournalEntry { path: path.clone(), backed_up: fs::symlink_metadata(&dest).is_ok() });
+		}
+		save_state(application_path)?;
+		let journal = InstallJournal { complete: false, state_saved: true, files };
+		journal.save(application_path)?;
+		Ok(Transaction { application_path: application_path.to_path_buf(), journal })
+	}
+
+	/// Moves the existing file at manifest path `path` into the rollback directory, so a new version can be placed.
+	pub fn back_up(&self, path: &str) -> Result<(), FileManagerError> {
+		let dest = resolve_path(&self.application_path, path)?;
+		if fs::symlink_metadata(&dest).is_err() {
+			return Ok(());
+		}
+		let backup = resolve_path(&get_rollback_dir(&self.application_path), path)?;
+		fs::create_dir_all(backup.parent().unwrap_or(&self.application_path))?;
+		fs::rename(&dest, &backup)?;
+		Ok(())
+	}
+
+	/// Marks the install as complete. The replaced files are kept, so it may still be rolled back.
+	pub fn commit(mut self) -> Result<(), FileManagerError> {
+		self.journal.complete = true;
+		self.journal.save(&self.application_path)
+	}
+
+	/// Rolls back every file written so far, restoring the previous versions.
+	pub fn abort(self) -> Result<Vec<String>, FileManagerError> {
+		rollback(&self.application_path)
+	}
+}
+
+/// Rolls back the last install into an application path, whether or not it completed, restoring the files it
+/// replaced, removing the files it added, and restoring the file index and release record from before it.
+/// Returns the manifest paths of the files rolled back.
+/// Files added outside of the install are not touched. Does nothing if there is no install to roll back.
+/// # Arguments
+/// * `application_path` - The application path.
+pub fn rollback(application_path: &Path) -> Result<Vec<String>, FileManagerError> {
+	let journal = match InstallJournal::load(application_path)? {
+		Some(journal) => journal,
+		None => return Ok(Vec::new()),
+	};
+	let rollback_dir = get_rollback_dir(application_path);
+	let mut restored = Vec::with_capacity(journal.files.len());
+	for entry in journal.files.iter().rev() {
+		let dest = resolve_path(application_path, &entry.path)?;
+		let backup = resolve_path(&rollback_dir, &entry.path)?;
+		let has_backup = fs::symlink_metadata(&backup).is_ok();
+		// Files which were backed up but not yet moved when the install stopped are still the previous version.
+		if entry.backed_up && !has_backup {
+			continue;
+		}
+		if fs::symlink_metadata(&dest).is_ok() {
+			fs::remove_file(&dest)?;
+		}
+		if has_backup {
+			fs::rename(&backup, &dest)?;
+		}
+		restored.push(entry.path.clone());
+	}
+	if journal.state_saved {
+		restore_state(application_path)?;
+	}
+	discard(application_path)?;
+	restored.reverse();
+	Ok(restored)
+}
+
+/// Rolls back the last install into an application path if it was interrupted.
+/// Returns the manifest paths of the files rolled back.
+pub fn recover(application_path: &Path) -> Result<Vec<String>, FileManagerError> {
+	match InstallJournal::load(application_path)? {
+		Some(ref journal) if !journal.complete => rollback(application_path),
+		_ => Ok(Vec::new()),
+	}
+}
+
+/// Gets the directory the state files are kept in during an install. Manifest paths can not include the state
+/// directory, so it never collides with a replaced file.
+fn get_state_backup_dir(application_path: &Path) -> PathBuf {
+	get_rollback_dir(application_path).join(STATE_DIR_NAME)
+}
+
+/// Copies the state files of an application path into the rollback directory. Missing state files are skipped.
+fn save_state(application_path: &Path) -> Result<(), FileManagerError> {
+	let backup_dir = get_state_backup_dir(application_path);
+	fs::create_dir_all(&backup_dir)?;
+	for name in &STATE_FILE_NAMES {
+		match fs::copy(application_path.join(STATE_DIR_NAME).join(name), backup_dir.join(name)) {
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+			result => {
+				result?;
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Restores the state files kept by `save_state`. State files which did not exist when the install began are removed.
+fn restore_state(application_path: &Path) -> Result<(), FileManagerError> {
+	let backup_dir = get_state_backup_dir(application_path);
+	for name in &STATE_FILE_NAMES {
+		let (backup, path) = (backup_dir.join(name), application_path.join(STATE_DIR_NAME).join(name));
+		if fs::symlink_metadata(&backup).is_ok() {
+			fs::rename(&backup, &path)?;
+			continue;
+		}
+		match fs::remove_file(&path) {
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+			result => result?,
+		}
+	}
+	Ok(())
+}
+
+/// Discards the journal and kept files of the last install, so it can no longer be rolled back.
+fn discard(application_path: &Path) -> Result<(), FileManagerError> {
+	let rollback_dir = get_rollback_dir(application_path);
+	if rollback_dir.exists() {
+		fs::remove_dir_all(&rollback_dir)?;
+	}
+	match fs::remove_file(InstallJournal::path(application_path)) {
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+		result => Ok(result?),
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_roll_back_installs() {
+		let dir = tempfile::tempdir().unwrap();
+		let app = dir.path();
+		fs::write(app.join("app.exe"), b"v1").unwrap();
+		fs::write(app.join("unrelated.txt"), b"user file").unwrap();
+		let paths = vec!["app.exe".to_owned(), "data/new.dat".to_owned()];
+
+		let transaction = Transaction::begin(app, &paths).unwrap();
+		for path in &paths {
+			transaction.back_up(path).unwrap();
+			let dest = app.join(path);
+			fs::create_dir_all(dest.parent().unwrap()).unwrap();
+			fs::write(dest, b"v2").unwrap();
+		}
+		transaction.commit().unwrap();
+		assert_eq!(fs::read(app.join("app.exe")).unwrap(), b"v2");
+
+		assert_eq!(rollback(app).unwrap(), paths);
+		assert_eq!(fs::read(app.join("app.exe")).unwrap(), b"v1");
+		assert!(!app.join("data/new.dat").exists());
+		assert!(app.join("unrelated.txt").exists());
+		assert!(rollback(app).unwrap().is_empty());
+	}
+
+	#[test]
+	fn should_recover_interrupted_installs() {
+		let dir = tempfile::tempdir().unwrap();
+		let app = dir.path();
+		fs::write(app.join("a.exe"), b"a1").unwrap();
+		fs::write(app.join("b.exe"), b"b1").unwrap();
+		let paths = vec!["a.exe".to_owned(), "b.exe".to_owned()];
+
+		// Interrupted after replacing the first file only.
+		let transaction = Transaction::begin(app, &paths).unwrap();
+		transaction.back_up("a.exe").unwrap();
+		fs::write(app.join("a.exe"), b"a2").unwrap();
+		drop(transaction);
+
+		assert_eq!(recover(app).unwrap(), vec!["a.exe".to_owned()]);
+		assert_eq!(fs::read(app.join("a.exe")).unwrap(), b"a1");
+		assert_eq!(fs::read(app.join("b.exe")).unwrap(), b"b1");
+		assert_eq!(InstallJournal::load(app).unwrap(), None);
+	}
+}
@@ -0,0 +1,57 @@
+// --- Imports
+use super::download::{DownloadJob, Downloader};
+use super::partial::PartialState;
+use super::FileManagerError;
+use std::fs;
+use std::path::Path;
+
+/// Gets the number of bytes needed on disk to download `jobs` with `downloader`.
+/// Files of unknown size are not counted. Space already taken by resumable partial downloads is deducted, as
+/// partial files are preallocated to their full size.
+pub fn required_space(jobs: &[DownloadJob], downloader: &Downloader) -> u64 {
+	jobs.iter()
+		.filter_map(|job| {
+			let size = job.file.size?;
+			let temp_path = downloader.temp_path(&job.file);
+			let reserved = match PartialState::load(&temp_path) {
+				Some(_) => fs::metadata(&temp_path).map(|m| m.len()).unwrap_or(0),
+				None => 0,
+			};
+			Some(size.saturating_sub(reserved))
+		})
+		.sum()
+}
+
+/// Checks that the filesystem containing `path` has at least `required` bytes available.
+/// # Arguments
+/// * `path` - An existing path on the target filesystem.
+/// * `required` - Number of bytes required.
+pub fn check_space(path: &Path, required: u64) -> Result<(), FileManagerError> {
+	let available = fs2::available_space(path)?;
+	if required > available {
+		return Err(FileManagerError::InsufficientSpace(path.to_string_lossy().into_owned(), required, available));
+	}
+	Ok(())
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_check_free_space() {
+		let dir = tempfile::tempdir().unwrap();
+
+		assert!(check_space(dir.path(), 0).is_ok());
+		match check_space(dir.path(), u64::MAX) {
+			Err(FileManagerError::InsufficientSpace(_, required, available)) => {
+				assert_eq!(required, u64::MAX);
+				assert!(available < required);
+			}
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
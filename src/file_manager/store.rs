@@ -0,0 +1,306 @@
+// --- Imports
+use super::hash::{self, FileHash, HashAlgorithm};
+use super::FileManagerError;
+use crate::config::Config;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// --- Consts
+/// Every algorithm blobs may be stored under.
+const ALGORITHMS: [HashAlgorithm; 3] = [HashAlgorithm::Md5, HashAlgorithm::Sha1, HashAlgorithm::Sha256];
+
+/// Defines how a stored blob was placed into an application path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+	Symlink,
+	Hardlink,
+	Reflink,
+	Copy,
+}
+
+/// Content-addressed file store.
+/// Blobs are stored once, keyed by their strongest hash, under `<root>/<algorithm>/<xx>/<digest>`, where `xx` is
+/// the first two digest characters. Application paths are populated with links to blobs, so several installs and
+/// release channels of the same application share identical files on disk.
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+	root: PathBuf,
+}
+
+impl ContentStore {
+	/// Opens the content store at `root`, creating it if needed.
+	/// # Arguments
+	/// * `root` - Path to the store's root directory.
+	pub fn open(root: &Path) -> Result<ContentStore, FileManagerError> {
+		fs::create_dir_all(root)?;
+		// Symlinks must not depend on the working directory.
+		Ok(ContentStore { root: root.canonicalize()? })
+	}
+
+	/// Opens the content store configured in `config`.
+	pub fn from_config(config: &Config) -> Result<ContentStore, FileManagerError> {
+		ContentStore::open(&config.get_storage_path()?)
+	}
+
+	/// Gets the root directory of the store.
+	pub fn root(&self) -> &Path {
+		&self.root
+	}
+
+	/// Gets the path at which the blob for `hash` is (or would be) stored.
+	/// Every `FileHash` holds a validated hex digest, so the path always lies inside the store.
+	pub fn blob_path(&self, hash: &FileHash) -> PathBuf {
+		self.root.join(hash.algorithm().to_string()).join(&hash.value()[..2]).join(hash.value())
+	}
+
+	/// Gets the hash of the blob at `path`, if `path` is the path of a blob in this store.
+	pub fn blob_hash(&self, path: &Path) -> Option<FileHash> {
+		let relative = path.strip_prefix(&self.root).ok()?;
+		let parts: Vec<&str> = relative.iter().map(|part| part.to_str()).collect::<Option<_>>()?;
+		match parts[..] {
+			[algorithm, prefix, value] => {
+				let algorithm = ALGORITHMS.iter().copied().find(|a| a.to_string() == algorithm)?;
+				FileHash::new(algorithm, value).filter(|hash| hash.value() == value && value[..2] == *prefix)
+			}
+			_ => None,
+		}
+	}
+
+	/// Lists every blob in the store with its path, sorted by path.
+	/// Other files in the store, ie temporary downloads and interrupted copies, are skipped.
+	pub fn blobs(&self) -> Result<Vec<(FileHash, PathBuf)>, FileManagerError> {
+		let mut blobs = Vec::new();
+		for algorithm in ALGORITHMS {
+			let algorithm_dir = self.root.join(algorithm.to_string());
+			if !algorithm_dir.is_dir() {
+				continue;
+			}
+			for prefix_dir in fs::read_dir(&algorithm_dir)? {
+				let prefix_dir = prefix_dir?;
+				if !prefix_dir.file_type()?.is_dir() {
+					continue;
+				}
+				for entry in fs::read_dir(prefix_dir.path())? {
+					let path = entry?.path();
+					if let Some(hash) = self.blob_hash(&path) {
+						blobs.push((hash, path));
+					}
+				}
+			}
+		}
+		blobs.sort_by(|a, b| a.1.cmp(&b.1));
+		Ok(blobs)
+	}
+
+	/// Returns true if a blob for `hash` is present in the store.
+	pub fn contains(&self, hash: &FileHash) -> bool {
+		self.blob_path(hash).is_file()
+	}
+
+	/// Moves the file at `src` into the store as the blob for `hash`, returning the blob path.
+	/// The file is re-hashed first, and rejected if it does not match. If the blob is already stored, `src` is
+	/// removed and the existing blob is kept.
+	/// # Arguments
+	/// * `hash` - The expected hash of the file.
+	/// * `src` - Path to the file. The file is consumed.
+	pub fn insert(&self, hash: &FileHash, src: &Path) -> Result<PathBuf, FileManagerError> {
+		let actual = hash::hash_file(src, hash.algorithm())?;
+		if actual != *hash {
+			return Err(FileManagerError::HashMismatch(src.to_string_lossy().into_owned(), hash.clone(), actual));
+		}
+		self.adopt(hash, src)
+	}
+
+	/// Moves the file at `src` into the store as the blob for `hash` without re-hashing it, returning the blob path.
+	/// Only use this for files whose hash was computed from their contents, ie while downloading.
+	/// # Arguments
+	/// * `hash` - The hash of the file.
+	/// * `src` - Path to the file. The file is consumed.
+	pub fn adopt(&self, hash: &FileHash, src: &Path) -> Result<PathBuf, FileManagerError> {
+		let blob_path = self.blob_path(hash);
+		if blob_path.is_file() {
+			fs::remove_file(src)?;
+			return Ok(blob_path);
+		}
+		fs::create_dir_all(blob_path.parent().unwrap_or(&self.root))?;
+		if fs::rename(src, &blob_path).is_err() {
+			// Cross-device moves are not supported by rename, so fall back to copying via a temporary name.
+			let partial_path = blob_path.with_extension("partial");
+			fs::copy(src, &partial_path)?;
+			fs::rename(&partial_path, &blob_path)?;
+			fs::remove_file(src)?;
+		}
+		// Blobs may be shared by several installs through links, so must never be modified in place.
+		let mut permissions = fs::metadata(&blob_path)?.permissions();
+		permissions.set_readonly(true);
+		fs::set_permissions(&blob_path, permissions)?;
+		Ok(blob_path)
+	}
+
+	/// Places the blob for `hash` at `dest`, replacing any existing file.
+	/// Symlinks are preferred, falling back to hardlinks, reflinks and finally copies where links are unavailable
+	/// (ie, unprivileged Windows users or stores on another filesystem). Returns the kind of link created.
+	/// # Arguments
+	/// * `hash` - Hash of the stored blob.
+	/// * `dest` - Destination path, usually within an application path.
+	pub fn materialize(&self, hash: &FileHash, dest: &Path) -> Result<LinkKind, FileManagerError> {
+		let blob_path = self.blob_path(hash);
+		if !blob_path.is_file() {
+			return Err(FileManagerError::MissingBlob(hash.clone()));
+		}
+		if let Some(parent) = dest.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		if fs::symlink_metadata(dest).is_ok() {
+			remove_file_or_link(dest)?;
+		}
+		if symlink(&blob_path, dest).is_ok() {
+			return Ok(LinkKind::Symlink);
+		}
+		if fs::hard_link(&blob_path, dest).is_ok() {
+			return Ok(LinkKind::Hardlink);
+		}
+		if reflink(&blob_path, dest).is_ok() {
+			return Ok(LinkKind::Reflink);
+		}
+		fs::copy(&blob_path, dest)?;
+		// Copies are private to the install, so may be writable.
+		make_writable(dest)?;
+		Ok(LinkKind::Copy)
+	}
+
+	/// Returns true if `dest` is a symlink to the blob for `hash`.
+	pub fn is_linked(&self, hash: &FileHash, dest: &Path) -> bool {
+		match fs::read_link(dest) {
+			Ok(target) => target == self.blob_path(hash),
+			Err(_) => false,
+		}
+	}
+
+	/// Removes the blob for `hash` from the store, if present.
+	pub fn remove(&self, hash: &FileHash) -> Result<(), FileManagerError> {
+		let blob_path = self.blob_path(hash);
+		if blob_path.is_file() {
+			remove_file_or_link(&blob_path)?;
+		}
+		Ok(())
+	}
+}
+
+/// Removes a file or link, clearing the read-only flag first where required on Windows.
+fn remove_file_or_link(path: &Path) -> io::Result<()> {
+	match fs::remove_file(path) {
+		Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied && cfg!(windows) => {
+			make_writable(path)?;
+			fs::remove_file(path)
+		}
+		result => result,
+	}
+}
+
+/// Clears the read-only flag of a file, granting write access to its owner only.
+#[cfg(unix)]
+fn make_writable(path: &Path) -> io::Result<()> {
+	use std::os::unix::fs::PermissionsExt;
+	let mut permissions = fs::metadata(path)?.permissions();
+	permissions.set_mode(permissions.mode() | 0o200);
+	fs::set_permissions(path, permissions)
+}
+
+#[cfg(windows)]
+fn make_writable(path: &Path) -> io::Result<()> {
+	let mut permissions = fs::metadata(path)?.permissions();
+	permissions.set_readonly(false);
+	fs::set_permissions(path, permissions)
+}
+
+#[cfg(unix)]
+fn symlink(src: &Path, dest: &Path) -> io::Result<()> {
+	std::os::unix::fs::symlink(src, dest)
+}
+
+#[cfg(windows)]
+fn symlink(src: &Path, dest: &Path) -> io::Result<()> {
+	std::os::windows::fs::symlink_file(src, dest)
+}
+
+/// Creates a copy-on-write clone of `src` at `dest`, on filesystems which support it (ie, btrfs and XFS).
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dest: &Path) -> io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+	// From linux/fs.h
+	const FICLONE: libc::c_ulong = 0x4004_9409;
+	let src_file = fs::File::open(src)?;
+	let dest_file = fs::File::create(dest)?;
+	// Safety: both file descriptors are valid for the duration of the call.
+	let result = unsafe { libc::ioctl(dest_file.as_raw_fd(), FICLONE as _, src_file.as_raw_fd()) };
+	if result != 0 {
+		let error = io::Error::last_os_error();
+		drop(dest_file);
+		let _ = fs::remove_file(dest);
+		return Err(error);
+	}
+	Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &Path, _dest: &Path) -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::Other, "reflinks are not supported on this platform"))
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::file_manager::hash::HashAlgorithm;
+
+	#[test]
+	fn should_store_and_materialize() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = ContentStore::open(&dir.path().join("store")).unwrap();
+		let download = dir.path().join("download.tmp");
+		fs::write(&download, b"abc").unwrap();
+		let hash = FileHash::new(HashAlgorithm::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+
+		let blob_path = store.insert(&hash, &download).unwrap();
+		assert!(store.contains(&hash));
+		assert!(!download.exists());
+		assert!(blob_path.ends_with("sha1/a9/a9993e364706816aba3e25717850c26c9cd0d89d"));
+
+		let first = dir.path().join("install-a/bin/app.exe");
+		let second = dir.path().join("install-b/bin/app.exe");
+		store.materialize(&hash, &first).unwrap();
+		// Existing files are replaced
+		fs::create_dir_all(second.parent().unwrap()).unwrap();
+		fs::write(&second, b"old").unwrap();
+		let kind = store.materialize(&hash, &second).unwrap();
+
+		assert_eq!(fs::read(&first).unwrap(), b"abc");
+		assert_eq!(fs::read(&second).unwrap(), b"abc");
+		if kind == LinkKind::Symlink {
+			assert!(store.is_linked(&hash, &second));
+		}
+	}
+
+	#[test]
+	fn should_reject_mismatched_blobs() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = ContentStore::open(dir.path()).unwrap();
+		let download = dir.path().join("download.tmp");
+		fs::write(&download, b"not abc").unwrap();
+		let hash = FileHash::new(HashAlgorithm::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+
+		match store.insert(&hash, &download) {
+			Err(FileManagerError::HashMismatch(_, expected, _)) => assert_eq!(expected, hash),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		assert!(!store.contains(&hash));
+		match store.materialize(&hash, &dir.path().join("app.exe")) {
+			Err(FileManagerError::MissingBlob(_)) => (),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,192 @@
+// --- Imports
+use crate::config::{Config, ConfigError, ManifestConfig, TimeWindow};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Shared limiter of the global download rate, and the rate it was created with.
+static GLOBAL_LIMITER: Mutex<Option<(u64, Arc<RateLimiter>)>> = Mutex::new(None);
+
+/// Token bucket rate limiter, shared between download workers.
+/// Up to one second of transfer may be taken as a burst. Workers which exceed the rate sleep until they are back
+/// within it, so the combined rate of every worker using the limiter converges on the limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+	rate: u64,
+	bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+impl RateLimiter {
+	/// Creates a limiter allowing `rate` bytes per second.
+	pub fn new(rate: u64) -> RateLimiter {
+		let rate = rate.max(1);
+		RateLimiter { rate, bucket: Mutex::new(Bucket { tokens: rate as f64, updated: Instant::now() }) }
+	}
+
+	/// Gets the rate allowed by this limiter, in bytes per second.
+	pub fn rate(&self) -> u64 {
+		self.rate
+	}
+
+	/// Takes `bytes` from the limiter, sleeping if the rate has been exceeded.
+	pub fn acquire(&self, bytes: u64) {
+		let wait = {
+			let mut bucket = self.bucket.lock().unwrap();
+			let now = Instant::now();
+			let refill = now.duration_since(bucket.updated).as_secs_f64() * self.rate as f64;
+			bucket.tokens = (bucket.tokens + refill).min(self.rate as f64) - bytes as f64;
+			bucket.updated = now;
+			match bucket.tokens < 0.0 {
+				true => Duration::from_secs_f64(-bucket.tokens / self.rate as f64),
+				false => Duration::from_secs(0),
+			}
+		};
+		if wait > Duration::from_secs(0) {
+			thread::sleep(wait);
+		}
+	}
+}
+
+/// Gets the process-wide limiter for `Config.max_download_rate`, shared by every download.
+/// Returns None if the global rate is unlimited.
+pub fn global_limiter(config: &Config) -> Option<Arc<RateLimiter>> {
+	let rate = config.max_download_rate?;
+	let mut global = GLOBAL_LIMITER.lock().unwrap();
+	match *global {
+		Some((global_rate, ref limiter)) if global_rate == rate => Some(limiter.clone()),
+		_ => {
+			let limiter = Arc::new(RateLimiter::new(rate));
+			*global = Some((rate, limiter.clone()));
+			Some(limiter)
+		}
+	}
+}
+
+/// Download rate limits applying to a downloader.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+	limiters: Vec<Arc<RateLimiter>>,
+	windows: Vec<TimeWindow>,
+}
+
+impl Throttle {
+	/// Creates a throttle which does not limit downloads.
+	pub fn unlimited() -> Throttle {
+		Default::default()
+	}
+
+	/// Creates a throttle from rate limiters, lifted during `windows`.
+	/// Every window is validated, so invalid times are reported before downloading starts.
+	pub fn new(limiters: Vec<Arc<RateLimiter>>, windows: Vec<TimeWindow>) -> Result<Throttle, ConfigError> {
+		for window in &windows {
+			window.contains(0)?;
+		}
+		Ok(Throttle { limiters, windows })
+	}
+
+	/// Creates the throttle for downloads of a manifest, applying both the global and the manifest's rate limits.
+	/// The global limit is shared with every other download in the process.
+	pub fn from_config(config: &Config, manifest_config: &ManifestConfig) -> Result<Throttle, ConfigError> {
+		let manifest_limiter = manifest_config.max_download_rate.map(|rate| Arc::new(RateLimiter::new(rate)));
+		let limiters = global_limiter(config).into_iter().chain(manifest_limiter).collect();
+		Throttle::new(limiters, config.unthrottled_windows.clone())
+	}
+
+	/// Returns true if downloads are currently limited.
+	pub fn is_limited(&self) -> bool {
+		!self.limiters.is_empty() && !self.is_unthrottled_at(local_minute_of_day())
+	}
+
+	/// Returns true if `minute`, a number of minutes since midnight, is within an unthrottled window.
+	pub fn is_unthrottled_at(&self, minute: u32) -> bool {
+		self.windows.iter().any(|window| window.contains(minute).unwrap_or(false))
+	}
+
+	/// Accounts for `bytes` downloaded, sleeping as needed to stay within every rate limit.
+	pub fn throttle(&self, bytes: u64) {
+		if self.is_limited() {
+			for limiter in &self.limiters {
+				limiter.acquire(bytes);
+			}
+		}
+	}
+}
+
+/// Gets the current local time as a number of minutes since midnight.
+#[cfg(unix)]
+fn local_minute_of_day() -> u32 {
+	// Safety: localtime_r only writes to the provided tm struct.
+	let tm = unsafe {
+		let now = libc::time(std::ptr::null_mut());
+		let mut tm: libc::tm = std::mem::zeroed();
+		libc::localtime_r(&now, &mut tm);
+		tm
+	};
+	(tm.tm_hour * 60 + tm.tm_min) as u32
+}
+
+#[cfg(windows)]
+fn local_minute_of_day() -> u32 {
+	// Safety: localtime_s only writes to the provided tm struct.
+	let tm = unsafe {
+		let now = libc::time(std::ptr::null_mut());
+		let mut tm: libc::tm = std::mem::zeroed();
+		libc::localtime_s(&mut tm, &now);
+		tm
+	};
+	(tm.tm_hour * 60 + tm.tm_min) as u32
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_limit_rate() {
+		let limiter = Arc::new(RateLimiter::new(10_000));
+		let start = Instant::now();
+
+		// The first second is available as a burst, the rest is shared between workers.
+		thread::scope(|scope| {
+			for _ in 0..4 {
+				let limiter = &limiter;
+				scope.spawn(move || {
+					for _ in 0..5 {
+						limiter.acquire(1000);
+					}
+				});
+			}
+		});
+
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_millis(900), "Finished too quickly: {:?}", elapsed);
+		assert!(elapsed < Duration::from_secs(3), "Finished too slowly: {:?}", elapsed);
+	}
+
+	#[test]
+	fn should_lift_limits_in_windows() {
+		let overnight = TimeWindow { start: "23:00".to_owned(), end: "07:00".to_owned() };
+		let throttle = Throttle::new(vec![Arc::new(RateLimiter::new(1))], vec![overnight]).unwrap();
+
+		assert!(throttle.is_unthrottled_at(23 * 60));
+		assert!(throttle.is_unthrottled_at(3 * 60 + 30));
+		assert!(!throttle.is_unthrottled_at(7 * 60));
+		assert!(!throttle.is_unthrottled_at(12 * 60));
+		assert!(!Throttle::unlimited().is_limited());
+
+		let invalid = TimeWindow { start: "25:00".to_owned(), end: "07:00".to_owned() };
+		match Throttle::new(Vec::new(), vec![invalid]) {
+			Err(ConfigError::InvalidConfig(_)) => (),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,283 @@
+// --- Imports
+use super::download::DownloadResult;
+use super::hash::{self, FileHash};
+use super::index::FileIndex;
+use super::install::{self, resolve_path};
+use super::mirror::MirrorTracker;
+use super::pool;
+use super::store::ContentStore;
+use super::{FileManagerError, STATE_DIR_NAME};
+use crate::config::{Config, ManifestConfig};
+use crate::manifest::manifest_spec::ManifestFile;
+use crate::session::PatchSession;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+/// Defines the state of a file on disk compared to its manifest entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileStatus {
+	/// The file matches the manifest.
+	Ok,
+	/// The file does not exist.
+	Missing,
+	/// The file's contents do not match the manifest. Contains the expected and actual hash.
+	Corrupt(FileHash, FileHash),
+	/// The file's size does not match the manifest. Contains the expected and actual size.
+	SizeMismatch(u64, u64),
+	/// The file exists in the application path but is not listed in the manifest.
+	Extra,
+	/// The file exists but could not be read (ie, it is locked by a running application). Contains the error.
+	Unreadable(String),
+}
+
+impl FileStatus {
+	/// Returns true if the file should be downloaded again.
+	pub fn needs_repair(&self) -> bool {
+		match *self {
+			FileStatus::Missing | FileStatus::Corrupt(_, _) | FileStatus::SizeMismatch(_, _) => true,
+			FileStatus::Ok | FileStatus::Extra | FileStatus::Unreadable(_) => false,
+		}
+	}
+}
+
+/// Defines the verification result of a single file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReport {
+	/// Path of the file, relative to the application path.
+	pub path: String,
+	pub status: FileStatus,
+}
+
+/// Compares the application path of `manifest_config` against `files`.
+/// Files are compared by size, then by their strongest hash unless `ignore_checksum` is set. Hashing runs in
+/// parallel. Returns one report per file in manifest order, followed by extra files sorted by path. Files with
+/// unsafe paths are not reported, as they are never installed.
+/// Unless `full` is set, files which are unchanged on disk since they were last verified are checked against the
+/// hash recorded in the file index instead of being hashed again. The index is updated with the results.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest the files belong to.
+/// * `files` - The manifest files.
+/// * `full` - If true, every file is hashed, regardless of the file index.
+pub fn verify_files(
+	config: &Config, manifest_config: &ManifestConfig, files: &[ManifestFile], full: bool,
+) -> Result<Vec<FileReport>, FileManagerError> {
+	let application_path = Path::new(&manifest_config.application_path);
+	let mut expected = HashSet::new();
+	let mut checks = Vec::with_capacity(files.len());
+	for file in files {
+		if let Ok(path) = resolve_path(application_path, &file.path) {
+			expected.insert(path.clone());
+			checks.push((file, path));
+		}
+	}
+	let mut index = FileIndex::load(application_path);
+	let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+	let verified = pool::parallel_map(checks, workers, |(file, path)| {
+		let known_index = match full {
+			true => None,
+			false => Some(&index),
+		};
+		let (status, hash) = verify_file(file, &path, !manifest_config.ignore_checksum, known_index);
+		(FileReport { path: file.path.clone(), status }, path, hash)
+	});
+	let mut reports = Vec::with_capacity(verified.len());
+	for (report, path, hash) in verified {
+		match hash {
+			Some(hash) => index.record(&report.path, &path, hash)?,
+			None if report.status != FileStatus::Ok => index.remove(&report.path),
+			None => (),
+		}
+		reports.push(report);
+	}
+	if application_path.is_dir() {
+		index.save(application_path)?;
+	}
+
+	// The content store may be configured inside the application path, and is not part of the install.
+	let store_path = config.get_storage_path().ok().and_then(|p| p.canonicalize().ok());
+	let mut extras = Vec::new();
+	if application_path.is_dir() {
+		find_extra_files(application_path, application_path, &expected, store_path.as_deref(), &mut extras)?;
+	}
+	extras.sort();
+	reports.extend(extras.into_iter().map(|path| FileReport { path, status: FileStatus::Extra }));
+	Ok(reports)
+}
+
+/// Downloads and installs every file whose report shows it needs repair, leaving other files untouched.
+/// Blobs in the content store which no longer match their hash are removed first, so they are not linked again.
+/// # Arguments
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest the files belong to.
+/// * `files` - The manifest files.
+/// * `reports` - Reports from `verify_files`.
+/// * `mirrors` - Mirror tracker used to rank the files' mirrors.
+/// * `session` - Session the repair belongs to.
+pub fn repair_files(
+	config: &Config, manifest_config: &ManifestConfig, files: &[ManifestFile], reports: &[FileReport],
+	mirrors: &Arc<MirrorTracker>, session: &PatchSession,
+) -> Result<Vec<DownloadResult>, FileManagerError> {
+	let needs_repair: HashSet<&str> =
+		reports.iter().filter(|r| r.status.needs_repair()).map(|r| r.path.as_str()).collect();
+	let bad_files: Vec<ManifestFile> = files.iter().filter(|f| needs_repair.contains(f.path.as_str())).cloned().collect();
+	if config.use_symlinked_storage {
+		let store = ContentStore::from_config(config)?;
+		for expected in bad_files.iter().filter_map(hash::strongest_hash) {
+			let blob_path = store.blob_path(&expected);
+			if store.contains(&expected) && hash::hash_file(&blob_path, expected.algorithm())? != expected {
+				store.remove(&expected)?;
+			}
+		}
+	}
+	install::install_files(config, manifest_config, &bad_files, mirrors, session)
+}
+
+/// Compares a single file on disk against its manifest entry.
+/// Returns the file's status, and its hash if it was hashed and found to match.
+/// # Arguments
+/// * `file` - The manifest file.
+/// * `path` - Location of the file on disk.
+/// * `verify_checksum` - If false, only the file's size is checked.
+/// * `index` - File index used to skip hashing unchanged files, if any.
+fn verify_file(
+	file: &ManifestFile, path: &Path, verify_checksum: bool, index: Option<&FileIndex>,
+) -> (FileStatus, Option<FileHash>) {
+	let metadata = match fs::metadata(path) {
+		Ok(metadata) if metadata.is_file() => metadata,
+		Ok(_) => return (FileStatus::Missing, None),
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => return (FileStatus::Missing, None),
+		Err(e) => return (FileStatus::Unreadable(e.to_string()), None),
+	};
+	if let Some(expected_size) = file.size {
+		if metadata.len() != expected_size {
+			return (FileStatus::SizeMismatch(expected_size, metadata.len()), None);
+		}
+	}
+	let expected = match hash::strongest_hash(file) {
+		Some(expected) if verify_checksum => expected,
+		_ => return (FileStatus::Ok, None),
+	};
+	if let Some(known) = index.and_then(|index| index.unchanged_hash(&file.path, &metadata)) {
+		if *known == expected {
+			return (FileStatus::Ok, None);
+		}
+	}
+	match hash::hash_file(path, expected.algorithm()) {
+		Ok(actual) if actual == expected => (FileStatus::Ok, Some(actual)),
+		Ok(actual) => (FileStatus::Corrupt(expected, actual), None),
+		Err(e) => (FileStatus::Unreadable(e.to_string()), None),
+	}
+}
+
+/// Recursively collects files under `dir` which are not in `expected`, as paths relative to `root`.
+/// Vanguard's state directory and the content store are skipped. Symlinked directories are not followed.
+fn find_extra_files(
+	root: &Path, dir: &Path, expected: &HashSet<PathBuf>, store_path: Option<&Path>, extras: &mut Vec<String>,
+) -> Result<(), FileManagerError> {
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let path = entry.path();
+		if entry.file_type()?.is_dir() {
+			let is_state_dir = dir == root && entry.file_name() == STATE_DIR_NAME;
+			let is_store = store_path.is_some_and(|store| path.canonicalize().ok().as_deref() == Some(store));
+			if !is_state_dir && !is_store {
+				find_extra_files(root, &path, expected, store_path, extras)?;
+			}
+		} else if !expected.contains(&path) {
+			let relative = path.strip_prefix(root).unwrap_or(&path);
+			let components: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+			extras.push(components.join("/"));
+		}
+	}
+	Ok(())
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::test_fixtures::{self, manifest_config};
+	use crate::test_server::{TestResponse, TestServer};
+
+	#[test]
+	fn should_verify_and_repair_files() {
+		let server = TestServer::start(|_| TestResponse::ok(b"abc"));
+		let dir = tempfile::tempdir().unwrap();
+		let app = dir.path().join("app");
+		let config = Config {
+			use_symlinked_storage: false,
+			storage_path: Some(dir.path().join("store").to_string_lossy().into_owned()),
+			..Default::default()
+		};
+		let manifest_config = manifest_config(&server.url("/Manifest.toml"), &app);
+		let file = |path: &str| test_fixtures::file(path, &server.url("/abc"), b"abc");
+		let files = [file("ok.exe"), file("bin/missing.dll"), file("corrupt.dat"), file("./short.dat")];
+		fs::create_dir_all(app.join("bin/plugins")).unwrap();
+		fs::create_dir_all(app.join(STATE_DIR_NAME)).unwrap();
+		fs::write(app.join("ok.exe"), b"abc").unwrap();
+		fs::write(app.join("corrupt.dat"), b"abd").unwrap();
+		fs::write(app.join("short.dat"), b"ab").unwrap();
+		fs::write(app.join("bin/plugins/extra.dll"), b"extra").unwrap();
+		fs::write(app.join(STATE_DIR_NAME).join("state"), b"state").unwrap();
+
+		let reports = verify_files(&config, &manifest_config, &files, false).unwrap();
+
+		let statuses: Vec<(&str, &FileStatus)> = reports.iter().map(|r| (r.path.as_str(), &r.status)).collect();
+		assert_eq!(statuses.len(), 5);
+		assert_eq!(statuses[0], ("ok.exe", &FileStatus::Ok));
+		assert_eq!(statuses[1], ("bin/missing.dll", &FileStatus::Missing));
+		match statuses[2] {
+			("corrupt.dat", FileStatus::Corrupt(_, ref actual)) => {
+				assert_ne!(Some(actual.value()), files[0].sha1.as_deref())
+			}
+			other => panic!("Unexpected status: {:?}", other),
+		}
+		assert_eq!(statuses[3], ("./short.dat", &FileStatus::SizeMismatch(3, 2)));
+		assert_eq!(statuses[4], ("bin/plugins/extra.dll", &FileStatus::Extra));
+
+		// Without checksums, only sizes are compared.
+		let unchecked = ManifestConfig { ignore_checksum: true, ..manifest_config.clone() };
+		assert_eq!(verify_files(&config, &unchecked, &files, false).unwrap()[2].status, FileStatus::Ok);
+
+		let mirrors = Arc::new(MirrorTracker::new());
+		let results = repair_files(&config, &manifest_config, &files, &reports, &mirrors, &PatchSession::default()).unwrap();
+
+		assert_eq!(results.len(), 3);
+		assert!(results.iter().all(|r| r.result.is_ok()));
+		let reports = verify_files(&config, &manifest_config, &files, false).unwrap();
+		assert!(reports.iter().all(|r| !r.status.needs_repair()));
+		assert!(app.join("bin/plugins/extra.dll").exists());
+	}
+
+	#[test]
+	fn should_skip_hashing_indexed_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let config = Config { storage_path: Some(dir.path().join("store").to_string_lossy().into_owned()), ..Default::default() };
+		let manifest_config = manifest_config("https://cdn.example.com/Manifest.toml", &dir.path().join("app"));
+		let app = Path::new(&manifest_config.application_path);
+		let files = [test_fixtures::file("app.exe", "https://cdn.example.com/app.exe", b"abc")];
+		let expected = hash::strongest_hash(&files[0]).unwrap();
+		fs::create_dir_all(app).unwrap();
+		// Corrupt contents with the same size, recorded in the index as verified.
+		fs::write(app.join("app.exe"), b"abd").unwrap();
+		let mut index = FileIndex::default();
+		index.record("app.exe", &app.join("app.exe"), expected).unwrap();
+		index.save(app).unwrap();
+
+		assert_eq!(verify_files(&config, &manifest_config, &files, false).unwrap()[0].status, FileStatus::Ok);
+		match verify_files(&config, &manifest_config, &files, true).unwrap()[0].status {
+			FileStatus::Corrupt(_, _) => (),
+			ref other => panic!("Unexpected status: {:?}", other),
+		}
+		// Failed files are dropped from the index, so the next quick verify hashes them.
+		assert!(FileIndex::load(app).entries.is_empty());
+		assert!(verify_files(&config, &manifest_config, &files, false).unwrap()[0].status.needs_repair());
+	}
+}
@@ -0,0 +1,12 @@
+// --- Modules
+pub mod config;
+pub mod events;
+pub mod file_manager;
+pub mod manifest;
+pub mod net;
+pub mod patcher;
+pub mod session;
+#[cfg(test)]
+mod test_fixtures;
+#[cfg(test)]
+mod test_server;
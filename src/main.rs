@@ -1,6 +1,5 @@
-// --- Modules
-pub mod config;
-pub mod manifest;
+// --- Imports
+use vanguard_rs::config;
 
 fn main() {
     let cfg = config::get_config();
@@ -0,0 +1,103 @@
+// --- Imports
+use crate::config::{self, ConfigError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// --- Consts
+/// Name of the manifest cache directory, stored next to `vanguard.toml`.
+pub const MANIFEST_CACHE_DIR_NAME: &str = "manifest_cache";
+
+/// Gets the path of the manifest cache directory as a PathBuf.
+pub fn get_cache_dir() -> Result<PathBuf, ConfigError> {
+	Ok(config::get_app_dir()?.join(MANIFEST_CACHE_DIR_NAME))
+}
+
+/// Defines the last fetched copy of a manifest or manifest index, with the validators needed to revalidate it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedDocument {
+	pub url: String,
+	/// Value of the `ETag` header the document was served with.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub etag: Option<String>,
+	/// Value of the `Last-Modified` header the document was served with.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub last_modified: Option<String>,
+	pub document: String,
+}
+
+/// Cache of fetched manifest documents, holding one file per URL.
+#[derive(Debug, Clone)]
+pub struct ManifestCache {
+	dir: PathBuf,
+}
+
+impl ManifestCache {
+	/// Opens the cache in `dir`. The directory is created when the first document is saved.
+	pub fn open(dir: &Path) -> ManifestCache {
+		ManifestCache { dir: dir.to_owned() }
+	}
+
+	/// Opens the cache next to `vanguard.toml`.
+	pub fn from_app_dir() -> Result<ManifestCache, ConfigError> {
+		Ok(ManifestCache::open(&get_cache_dir()?))
+	}
+
+	/// Loads the cached document for `url`.
+	/// The cache is only an optimisation, so a missing or unreadable entry yields None.
+	pub fn load(&self, url: &str) -> Option<CachedDocument> {
+		let contents = fs::read_to_string(self.entry_path(url)).ok()?;
+		let cached: CachedDocument = toml::from_str(&contents).ok()?;
+		match cached.url == url {
+			true => Some(cached),
+			false => None,
+		}
+	}
+
+	/// Saves `cached`, replacing any existing entry for its URL.
+	pub fn save(&self, cached: &CachedDocument) -> Result<(), io::Error> {
+		let contents = toml::to_string(cached).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		fs::create_dir_all(&self.dir)?;
+		// Written beside the entry and renamed, so an interrupted save never leaves a truncated document.
+		let path = self.entry_path(&cached.url);
+		let part_path = path.with_extension("part");
+		fs::write(&part_path, contents)?;
+		fs::rename(&part_path, &path)
+	}
+
+	/// Gets the path of the entry for `url`, named by the URL's SHA-256 hash.
+	fn entry_path(&self, url: &str) -> PathBuf {
+		self.dir.join(format!("{}.toml", hex::encode(Sha256::digest(url.as_bytes()))))
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_save_and_load_documents() {
+		let dir = tempfile::tempdir().unwrap();
+		let cache = ManifestCache::open(&dir.path().join("cache"));
+		let url = "https://cdn.example.com/manifest.toml";
+		assert_eq!(cache.load(url), None);
+
+		let cached = CachedDocument {
+			url: url.to_owned(),
+			etag: Some("\"v1\"".to_owned()),
+			last_modified: None,
+			document: "version = \"vg-1.0\"\nlabel = \"App\"\n".to_owned(),
+		};
+		cache.save(&cached).unwrap();
+		assert_eq!(cache.load(url), Some(cached.clone()));
+		assert_eq!(cache.load("https://cdn.example.com/other.toml"), None);
+
+		fs::write(cache.entry_path(url), "not toml").unwrap();
+		assert_eq!(cache.load(url), None);
+	}
+}
@@ -0,0 +1,132 @@
+// --- Imports
+use super::ManifestError;
+use serde::{Deserialize, Serialize};
+
+// --- Consts
+/// Version identifier for manifest index documents.
+pub const VG_INDEX_1_0_VERSION: &str = "vg-index-1.0";
+
+/// Defines a Manifest index, which lists the release channels published for an application.
+/// An index is referenced by `ManifestConfig.url` in place of a manifest when an application publishes
+/// several channels (ie, stable, beta and dev), each with its own manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestIndex {
+	/// Version identifier (ie, vg-index-1.0).
+	pub version: String,
+	/// Global application name for the index.
+	pub label: String,
+	/// Channel used when no channel is selected in config. Falls back to the first listed channel if missing.
+	pub default_channel: Option<String>,
+	/// List of release channels.
+	#[serde(rename = "channel")]
+	pub channels: Vec<ManifestChannel>,
+}
+
+/// Defines a release channel and the manifest which describes it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestChannel {
+	/// Channel name, used for selection in config (ie, stable).
+	pub name: String,
+	/// URL of the channel's manifest.
+	pub url: String,
+	/// Human readable channel description for GUI launchers.
+	pub description: Option<String>,
+}
+
+impl ManifestIndex {
+	/// Finds the channel to use for the given selection, returning the index default if `channel` is `None`.
+	/// # Arguments
+	/// * `channel` - The selected channel name, if any.
+	pub fn resolve_channel(&self, channel: Option<&str>) -> Result<&ManifestChannel, ManifestError> {
+		match channel.or(self.default_channel.as_deref()) {
+			Some(name) => self
+				.channels
+				.iter()
+				.find(|c| c.name == name)
+				.ok_or_else(|| ManifestError::UnknownChannel(name.to_owned())),
+			None => self.channels.first().ok_or(ManifestError::MissingRequiredValue("channel")),
+		}
+	}
+}
+
+/// Returns true if the contents of a TOML document declare it to be a manifest index rather than a manifest.
+/// # Arguments
+/// * `document` - A string slice containing the document contents
+pub fn is_index(document: &str) -> bool {
+	#[derive(Deserialize)]
+	struct IndexVersion<'a> {
+		version: &'a str,
+	}
+	match toml::from_str::<IndexVersion>(document) {
+		Ok(v) => v.version == VG_INDEX_1_0_VERSION,
+		Err(_) => false,
+	}
+}
+
+/// Serializes a `ManifestIndex` into a Vanguard index 1.0 (`vg-index-1.0`) TOML format.
+/// # Arguments
+/// * `index` - The ManifestIndex object.
+pub fn serialize_index(index: &ManifestIndex) -> Result<String, ManifestError> {
+	let mut versioned_index = index.clone();
+	versioned_index.version = VG_INDEX_1_0_VERSION.to_owned();
+	Ok(toml::to_string(&versioned_index)?)
+}
+
+/// Deserializes the contents of a manifest index file, returning a `ManifestIndex`.
+/// # Arguments
+/// * `index` - A string slice containing the index file contents
+pub fn deserialize_index(index: &str) -> Result<ManifestIndex, ManifestError> {
+	let index: ManifestIndex = toml::from_str(index)?;
+	if index.version != VG_INDEX_1_0_VERSION {
+		return Err(ManifestError::UnknownType);
+	}
+	Ok(index)
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	const TEST_INDEX: &str = r#"
+		version = "vg-index-1.0"
+		label = "Test Application"
+		default_channel = "stable"
+
+		[[channel]]
+		name = "stable"
+		url = "https://example.com/stable/Manifest.toml"
+
+		[[channel]]
+		name = "beta"
+		url = "https://example.com/beta/Manifest.toml"
+		description = "Weekly preview builds"
+	"#;
+
+	#[test]
+	fn should_deserialize() {
+		assert!(is_index(TEST_INDEX));
+		let deser = deserialize_index(TEST_INDEX).unwrap();
+
+		assert_eq!(deser.label, "Test Application");
+		assert_eq!(deser.default_channel.as_ref().unwrap(), "stable");
+		assert_eq!(deser.channels[0].name, "stable");
+		assert_eq!(deser.channels[0].url, "https://example.com/stable/Manifest.toml");
+		assert_eq!(deser.channels[1].name, "beta");
+		assert_eq!(deser.channels[1].description.as_ref().unwrap(), "Weekly preview builds");
+	}
+
+	#[test]
+	fn should_resolve_channels() {
+		let index = deserialize_index(TEST_INDEX).unwrap();
+
+		assert_eq!(index.resolve_channel(None).unwrap().name, "stable");
+		assert_eq!(index.resolve_channel(Some("beta")).unwrap().name, "beta");
+		match index.resolve_channel(Some("nightly")) {
+			Err(ManifestError::UnknownChannel(name)) => assert_eq!(name, "nightly"),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,96 @@
+// --- Imports
+use super::manifest_spec::{Manifest, ManifestFile};
+use std::collections::{HashMap, HashSet};
+
+/// Defines the minimal set of file changes required to move an install from one Manifest to another,
+/// ie when switching release channels.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestDiff {
+	/// Files present in the target manifest only.
+	pub added: Vec<ManifestFile>,
+	/// Files present in both manifests whose contents differ. Entries are taken from the target manifest.
+	pub changed: Vec<ManifestFile>,
+	/// Paths present in the source manifest only, which should be removed from disk.
+	pub removed: Vec<String>,
+	/// Paths whose contents are identical in both manifests.
+	pub unchanged: Vec<String>,
+}
+
+impl ManifestDiff {
+	/// Returns true if no files need to be downloaded or removed.
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+	}
+
+	/// Iterates over every file which must be downloaded to apply the diff.
+	pub fn files_to_fetch(&self) -> impl Iterator<Item = &ManifestFile> {
+		self.added.iter().chain(self.changed.iter())
+	}
+}
+
+/// Computes the file changes required to move an install from manifest `from` to manifest `to`.
+/// Files are matched by path; see `ManifestFile::is_same_content` for how contents are compared.
+/// # Arguments
+/// * `from` - The currently installed Manifest.
+/// * `to` - The target Manifest.
+pub fn diff_manifests(from: &Manifest, to: &Manifest) -> ManifestDiff {
+	let current: HashMap<&str, &ManifestFile> = from.files.iter().map(|f| (f.path.as_str(), f)).collect();
+	let mut diff = ManifestDiff::default();
+
+	for file in &to.files {
+		match current.get(file.path.as_str()) {
+			None => diff.added.push(file.clone()),
+			Some(old) if old.is_same_content(file) => diff.unchanged.push(file.path.clone()),
+			Some(_) => diff.changed.push(file.clone()),
+		}
+	}
+
+	let target: HashSet<&str> = to.files.iter().map(|f| f.path.as_str()).collect();
+	diff.removed = from.files.iter().filter(|f| !target.contains(f.path.as_str())).map(|f| f.path.clone()).collect();
+
+	diff
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::test_fixtures::manifest;
+
+	fn file(path: &str, sha1: Option<&str>, size: Option<u64>) -> ManifestFile {
+		ManifestFile {
+			path: path.to_owned(),
+			url: vec![format!("https://example.download.mirror/{}", path)],
+			size,
+			md5: None,
+			sha1: sha1.map(String::from),
+			sha256: None,
+		}
+	}
+
+	#[test]
+	fn should_diff_manifests() {
+		let stable = manifest(vec![
+			file("app.exe", Some("aaaa"), Some(256)),
+			file("lib/shared.dll", Some("bbbb"), Some(512)),
+			file("lib/stable-only.dll", Some("cccc"), None),
+			file("readme.txt", None, Some(10)),
+		]);
+		let beta = manifest(vec![
+			file("app.exe", Some("dddd"), Some(256)),
+			file("lib/shared.dll", Some("BBBB"), Some(512)),
+			file("lib/beta-only.dll", Some("eeee"), None),
+			file("readme.txt", None, Some(10)),
+		]);
+		let diff = diff_manifests(&stable, &beta);
+
+		assert_eq!(diff.added.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["lib/beta-only.dll"]);
+		assert_eq!(diff.changed.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["app.exe", "readme.txt"]);
+		assert_eq!(diff.removed, vec!["lib/stable-only.dll"]);
+		assert_eq!(diff.unchanged, vec!["lib/shared.dll"]);
+		assert!(!diff.is_empty());
+		assert_eq!(diff.files_to_fetch().count(), 3);
+	}
+}
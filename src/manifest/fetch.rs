@@ -0,0 +1,199 @@
+// --- Imports
+use super::cache::{CachedDocument, ManifestCache};
+use super::manifest_spec::Manifest;
+use super::{channel, ManifestError};
+use crate::config::{Config, ManifestConfig, RetryPolicy};
+use crate::events::{EventSink, PatchEvent, Phase};
+use crate::net::client::Client;
+use crate::net::{self, retry, NetError};
+use serde::{Deserialize, Serialize};
+
+// --- Consts
+/// Connection timeout for manifest requests, in milliseconds.
+const CONNECT_TIMEOUT_MS: u64 = 15_000;
+/// Read timeout for manifest requests, in milliseconds.
+const READ_TIMEOUT_MS: u64 = 30_000;
+
+/// Defines where a fetched manifest came from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ManifestSource {
+	/// The manifest was downloaded and differs from the cached copy, if any.
+	Downloaded,
+	/// The cached manifest is current, so no update is available.
+	NotModified,
+	/// The server could not be reached, so the cached manifest was used.
+	Offline,
+}
+
+/// Defines a fetched manifest and where it came from.
+#[derive(Debug, Clone)]
+pub struct FetchedManifest {
+	pub manifest: Manifest,
+	pub source: ManifestSource,
+}
+
+/// Fetches the manifest configured by `manifest_config`.
+/// If the configured URL serves a manifest index, the configured channel's manifest is fetched from it. Both
+/// requests are subject to the secure patching policy of `manifest_config`, and are retried under `Config.retry`.
+/// Documents are cached in `cache` and revalidated with conditional requests. If the server cannot be reached, the
+/// cached documents are used instead.
+/// # Arguments
+/// * `client` - Client used to send requests.
+/// * `config` - Application config.
+/// * `manifest_config` - Config of the manifest to fetch.
+/// * `cache` - Cache of previously fetched documents.
+/// * `events` - Sink which is told of retries, and when the manifest has been fetched.
+pub fn fetch_manifest(
+	client: &Client, config: &Config, manifest_config: &ManifestConfig, cache: &ManifestCache, events: &EventSink,
+) -> Result<FetchedManifest, ManifestError> {
+	events.emit(PatchEvent::PhaseChanged(Phase::FetchingManifest));
+	let allow_insecure = manifest_config.allow_insecure_patching;
+	let fetch = |url: &str| fetch_document(client, cache, url, allow_insecure, &config.retry, events);
+	let (mut document, mut source) = fetch(&manifest_config.url)?;
+	if channel::is_index(&document) {
+		let index = channel::deserialize_index(&document)?;
+		let selected = index.resolve_channel(manifest_config.channel.as_deref())?;
+		let (channel_document, channel_source) = fetch(&selected.url)?;
+		document = channel_document;
+		source = match source {
+			ManifestSource::Offline => ManifestSource::Offline,
+			_ => channel_source,
+		};
+	}
+	let manifest = super::deserialize_manifest(&document)?;
+	let fetched = PatchEvent::ManifestFetched(manifest_config.url.clone(), manifest.label.clone(), manifest.files.len());
+	events.emit(fetched);
+	Ok(FetchedManifest { manifest, source })
+}
+
+/// Fetches the document at `url` as a string, retrying transient failures.
+/// A cached copy of the document is revalidated rather than downloaded again, and is returned if the server cannot
+/// be reached. Documents which are downloaded replace the cached copy.
+fn fetch_document(
+	client: &Client, cache: &ManifestCache, url: &str, allow_insecure: bool, policy: &RetryPolicy, events: &EventSink,
+) -> Result<(String, ManifestSource), ManifestError> {
+	let cached = cache.load(url);
+	let mut headers = Vec::new();
+	if let Some(ref cached) = cached {
+		if let Some(ref etag) = cached.etag {
+			headers.push(("If-None-Match", etag.as_str()));
+		}
+		if let Some(ref last_modified) = cached.last_modified {
+			headers.push(("If-Modified-Since", last_modified.as_str()));
+		}
+	}
+	let result = retry::with_retries(policy, url, events, || {
+		let timeout_ms = (CONNECT_TIMEOUT_MS, READ_TIMEOUT_MS);
+		let response = net::get_with_headers(client, url, allow_insecure, timeout_ms, &headers)?;
+		let etag = response.header("etag").map(str::to_owned);
+		let last_modified = response.header("last-modified").map(str::to_owned);
+		let document = response.into_string().map_err(|e| NetError::Network(url.to_owned(), e.to_string()))?;
+		Ok(CachedDocument { url: url.to_owned(), etag, last_modified, document })
+	});
+	match (result, cached) {
+		(Ok(fetched), cached) => {
+			let source = match cached {
+				Some(ref cached) if cached.document == fetched.document => ManifestSource::NotModified,
+				_ => ManifestSource::Downloaded,
+			};
+			if let Err(e) = cache.save(&fetched) {
+				log::warn!("Could not cache {} - {}", url, e);
+			}
+			Ok((fetched.document, source))
+		}
+		(Err(NetError::Http(_, 304, _)), Some(cached)) => Ok((cached.document, ManifestSource::NotModified)),
+		(Err(NetError::Network(_, ref desc)), Some(cached)) => {
+			log::warn!("Could not reach {} - using cached copy ({})", url, desc);
+			Ok((cached.document, ManifestSource::Offline))
+		}
+		(Err(e), _) => Err(e.into()),
+	}
+}
+
+impl From<NetError> for ManifestError {
+	fn from(item: NetError) -> Self {
+		ManifestError::Net(item)
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::test_fixtures;
+	use crate::test_server::{TestResponse, TestServer};
+	use std::path::Path;
+
+	fn manifest_config(url: String, allow_insecure_patching: bool) -> ManifestConfig {
+		let manifest_config = test_fixtures::manifest_config(&url, Path::new("app"));
+		ManifestConfig { channel: Some("beta".to_owned()), allow_insecure_patching, ..manifest_config }
+	}
+
+	#[test]
+	fn should_fetch_manifest_from_index() {
+		let manifests = TestServer::start(|_| TestResponse::ok(b"version = \"vg-1.1\"\nlabel = \"App Beta\"\n"));
+		let index = format!(
+			"version = \"vg-index-1.0\"\nlabel = \"App\"\n\n[[channel]]\nname = \"beta\"\nurl = \"{}\"\n",
+			manifests.url("/beta.toml")
+		);
+		let indexes = TestServer::start(move |_| TestResponse::ok(index.as_bytes()));
+		let client = Client::default();
+		let config = Config::default();
+		let dir = tempfile::tempdir().unwrap();
+		let cache = ManifestCache::open(dir.path());
+
+		let (events, received) = EventSink::channel();
+		let config_url = indexes.url("/index.toml");
+		let fetched = fetch_manifest(&client, &config, &manifest_config(config_url.clone(), true), &cache, &events);
+		let fetched = fetched.unwrap();
+		assert_eq!(fetched.manifest.label, "App Beta");
+		assert_eq!(fetched.source, ManifestSource::Downloaded);
+		let event = PatchEvent::ManifestFetched(config_url.clone(), "App Beta".to_owned(), 0);
+		assert_eq!(received.try_iter().last(), Some(event));
+		assert_eq!(manifests.hits("/beta.toml"), 1);
+
+		match fetch_manifest(&client, &config, &manifest_config(config_url, false), &cache, &events) {
+			Err(ManifestError::Net(NetError::InsecureUrl(_))) => assert_eq!(indexes.requests().len(), 1),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn should_revalidate_cached_manifests() {
+		let server = TestServer::start(|request| match request.headers.get("if-none-match").map(String::as_str) {
+			Some("\"v1\"") => TestResponse::status(304),
+			_ => TestResponse::ok(b"version = \"vg-1.1\"\nlabel = \"App\"\n").header("ETag", "\"v1\""),
+		});
+		let client = Client::default();
+		let mut config = Config::default();
+		config.retry.max_attempts = 1;
+		let dir = tempfile::tempdir().unwrap();
+		let cache = ManifestCache::open(dir.path());
+		let events = EventSink::default();
+
+		let config_url = server.url("/manifest.toml");
+		let fetch = |url: &str| {
+			fetch_manifest(&client, &config, &manifest_config(url.to_owned(), true), &cache, &events)
+		};
+		assert_eq!(fetch(&config_url).unwrap().source, ManifestSource::Downloaded);
+		let fetched = fetch(&config_url).unwrap();
+		assert_eq!((fetched.manifest.label.as_str(), fetched.source), ("App", ManifestSource::NotModified));
+		assert_eq!(server.requests()[1].headers.get("if-none-match").map(String::as_str), Some("\"v1\""));
+
+		// A closed port stands in for an unreachable server.
+		let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+		let offline_url = format!("http://127.0.0.1:{}/manifest.toml", port);
+		let offline_entry = CachedDocument { url: offline_url.clone(), ..cache.load(&config_url).unwrap() };
+		cache.save(&offline_entry).unwrap();
+		let fetched = fetch(&offline_url).unwrap();
+		assert_eq!((fetched.manifest.label.as_str(), fetched.source), ("App", ManifestSource::Offline));
+
+		let uncached_url = format!("http://127.0.0.1:{}/uncached.toml", port);
+		match fetch(&uncached_url) {
+			Err(ManifestError::Net(NetError::Network(_, _))) => (),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
@@ -1,17 +1,30 @@
 // --- Modules
 pub mod tq_xml;
 pub mod vg_1_0;
+pub mod vg_1_1;
+
+// --- Imports
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 
 /// Defines a Manifest.
 /// This type is a superset of all versioned Manifest types, and takes ownership
 /// of data deserialized with underlying versioned types so deserializers can fall out of scope.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Manifest {
 	/// Version identifier (ie, vg-1.0).
 	/// Tequila does not sepifcy a version attribute, so `tq-xml` is used for all Tequila XML manifests.
 	pub version: String,
 	/// Global application name for the manifest.
 	pub label: String,
+	/// Application version described by the manifest (ie, 1.5.0).
+	pub app_version: Option<String>,
+	/// Monotonically increasing build number of the application.
+	pub build: Option<u64>,
+	/// Release date of the application version, as an ISO 8601 date (ie, 2020-03-14).
+	pub release_date: Option<String>,
+	/// Release notes for the application version.
+	pub changelog: Option<String>,
 	/// URL of the webpage for the application.
 	pub webpage: Option<String>,
 	/// URL of the forums for the application.
@@ -28,8 +41,64 @@ pub struct Manifest {
 	pub files: Vec<ManifestFile>,
 }
 
+impl Manifest {
+	/// Compares the application release described by this manifest against `other`.
+	/// Build numbers are compared if both manifests provide one, falling back to comparing `app_version`
+	/// component-wise. Returns `None` if the manifests do not carry enough metadata to be ordered.
+	pub fn compare_release(&self, other: &Manifest) -> Option<Ordering> {
+		if let (Some(a), Some(b)) = (self.build, other.build) {
+			return Some(a.cmp(&b));
+		}
+		match (self.app_version.as_deref(), other.app_version.as_deref()) {
+			(Some(a), Some(b)) => Some(compare_versions(a, b)),
+			_ => None,
+		}
+	}
+
+	/// Returns true if this manifest is known to describe an older release than `installed`.
+	pub fn is_downgrade_from(&self, installed: &Manifest) -> bool {
+		self.compare_release(installed) == Some(Ordering::Less)
+	}
+}
+
+/// Compares two dotted version strings (ie, 1.4.2 and 1.10.0) component-wise.
+/// Numeric components are compared numerically and any other components lexically. A leading `v` is ignored,
+/// missing numeric components are treated as zero (1.5 == 1.5.0), and pre-release suffixes sort before the
+/// release they precede (1.5.0-beta < 1.5.0).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+	let a: Vec<&str> = a.trim_start_matches(['v', 'V']).split(['.', '-']).collect();
+	let b: Vec<&str> = b.trim_start_matches(['v', 'V']).split(['.', '-']).collect();
+	for i in 0..a.len().max(b.len()) {
+		let ordering = match (a.get(i), b.get(i)) {
+			(Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+				(Ok(x), Ok(y)) => x.cmp(&y),
+				(Ok(_), Err(_)) => Ordering::Greater,
+				(Err(_), Ok(_)) => Ordering::Less,
+				(Err(_), Err(_)) => x.cmp(y),
+			},
+			(Some(x), None) => compare_to_missing(x),
+			(None, Some(y)) => compare_to_missing(y).reverse(),
+			(None, None) => Ordering::Equal,
+		};
+		if ordering != Ordering::Equal {
+			return ordering;
+		}
+	}
+	Ordering::Equal
+}
+
+/// Compares a version component against a missing component in the other version.
+fn compare_to_missing(component: &str) -> Ordering {
+	match component.parse::<u64>() {
+		Ok(0) => Ordering::Equal,
+		Ok(_) => Ordering::Greater,
+		// A trailing non-numeric component marks a pre-release
+		Err(_) => Ordering::Less,
+	}
+}
+
 /// Defines a launchable application profile
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ManifestProfile {
 	/// Profile name.
 	pub name: String,
@@ -46,18 +115,58 @@ pub struct ManifestProfile {
 }
 
 /// Defines a patchable file. MD5, SHA1, or SHA256 is required for secure patching.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ManifestFile {
 	/// Filepath of the file on disk, relative to app dir.
 	pub path: String,
 	/// URL(s) to retrieve the file from.
 	pub url: Vec<String>,
 	/// Size in bytes of the file.
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub size: Option<u64>,
 	/// MD5 hash of the file.
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub md5: Option<String>,
 	/// SHA1 hash of the file.
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub sha1: Option<String>,
 	/// SHA256 hash of the file.
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub sha256: Option<String>,
 }
+
+impl ManifestFile {
+	/// Returns true if `other` is known to have identical contents to this file.
+	/// The strongest hash present on both files is compared. Files without a common hash can not be proven
+	/// identical and are always treated as different, even if their sizes match.
+	pub fn is_same_content(&self, other: &ManifestFile) -> bool {
+		if let (Some(a), Some(b)) = (self.size, other.size) {
+			if a != b {
+				return false;
+			}
+		}
+		let hashes = [(&self.sha256, &other.sha256), (&self.sha1, &other.sha1), (&self.md5, &other.md5)];
+		match hashes.iter().find_map(|(a, b)| a.as_ref().zip(b.as_ref())) {
+			Some((a, b)) => a.eq_ignore_ascii_case(b),
+			None => false,
+		}
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_compare_versions() {
+		assert_eq!(compare_versions("1.4.2", "1.5.0"), Ordering::Less);
+		assert_eq!(compare_versions("1.10.0", "1.9.9"), Ordering::Greater);
+		assert_eq!(compare_versions("v1.5", "1.5.0"), Ordering::Equal);
+		assert_eq!(compare_versions("1.5.0-beta", "1.5.0"), Ordering::Less);
+		assert_eq!(compare_versions("1.5.0-beta.2", "1.5.0-beta.10"), Ordering::Less);
+		assert_eq!(compare_versions("1.5.0-alpha", "1.5.0-beta"), Ordering::Less);
+	}
+}
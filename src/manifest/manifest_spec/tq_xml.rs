@@ -1,3 +1,6 @@
+// Tags are matched in the shape of the Tequila schema, which lists further tags that may be supported later.
+#![allow(clippy::single_match)]
+
 // --- Imports
 use super::{Manifest, ManifestFile, ManifestProfile};
 use super::super::ManifestError;
@@ -53,6 +56,10 @@ pub fn deserialize_manifest(manifest: &str) -> Result<Manifest, ManifestError> {
 	let mut manifest = Manifest {
 		version: TQ_VERSION.to_owned(),
 		label: "None".to_owned(),
+		app_version: None,
+		build: None,
+		release_date: None,
+		changelog: None,
 		profiles: Vec::<ManifestProfile>::with_capacity(INITIAL_PROFILE_ALLOC),
 		files: Vec::<ManifestFile>::with_capacity(INITAL_FILE_ALLOC),
 		webpage: None,
@@ -190,7 +197,7 @@ mod tests {
 				<poster_image url="https://example.com/some-image.png" />
 			</manifest>
 		"#;
-		let deser = deserialize_manifest(&test_xml).unwrap();
+		let deser = deserialize_manifest(test_xml).unwrap();
 
 		assert_eq!(deser.label, "Test Manifest");
 
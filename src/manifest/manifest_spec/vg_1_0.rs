@@ -2,6 +2,7 @@
 use super::{Manifest, ManifestFile, ManifestProfile};
 use super::super::ManifestError;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 /// Version identifier
 const VG_1_0_VERSION: &str = "vg-1.0";
@@ -10,10 +11,10 @@ const VG_1_0_VERSION: &str = "vg-1.0";
 /// Note that properties not supported in `vg-1.0` will be silently dropped.
 /// # Arguments
 /// * `manifest` - The Manifest object.
-pub fn serialize_manifest<'a>(manifest: &'a Manifest) -> Result<String, ManifestError> {
+pub fn serialize_manifest(manifest: &Manifest) -> Result<String, ManifestError> {
 	// Cast to the versioned struct and overwrite the version property.
 	let mut versioned_manifest: Manifest_VG_1_0 = manifest.into();
-	versioned_manifest.version = VG_1_0_VERSION;
+	versioned_manifest.version = Cow::Borrowed(VG_1_0_VERSION);
 	// Serialize
 	let serialized = toml::to_string(&versioned_manifest)?;
 	Ok(serialized)
@@ -23,8 +24,7 @@ pub fn serialize_manifest<'a>(manifest: &'a Manifest) -> Result<String, Manifest
 /// # Arguments
 /// * `manifest` - A string slice containing the manifest file contents
 pub fn deserialize_manifest(manifest: &str) -> Result<Manifest, ManifestError> {
-	println!("{}", manifest);
-	let versioned_manifest: Manifest_VG_1_0 = toml::from_str(manifest).unwrap();
+	let versioned_manifest: Manifest_VG_1_0 = toml::from_str(manifest)?;
 	Ok(versioned_manifest.into())
 }
 
@@ -34,24 +34,26 @@ pub fn deserialize_manifest(manifest: &str) -> Result<Manifest, ManifestError> {
 #[allow(non_camel_case_types)]
 pub struct Manifest_VG_1_0<'a> {
 	/// Version identifier (ie, vg-1.0).
-	pub version: &'a str,
+	#[serde(borrow)]
+	pub version: Cow<'a, str>,
 	/// Global application name for the manifest.
-	pub label: &'a str,
+	#[serde(borrow)]
+	pub label: Cow<'a, str>,
 	/// URL of the webpage for the application.
-	pub webpage: Option<&'a str>,
+	pub webpage: Option<Cow<'a, str>>,
 	/// URL of the forums for the application.
-	pub forums: Option<&'a str>,
+	pub forums: Option<Cow<'a, str>>,
 	/// Discord invite link for the application's Discord community.
-	pub discord: Option<&'a str>,
+	pub discord: Option<Cow<'a, str>>,
 	/// URL for an RSS news feed for the application.
-	pub rss: Option<&'a str>,
+	pub rss: Option<Cow<'a, str>>,
 	/// URL of a banner image to display on GUI launchers.
-	pub poster_image: Option<&'a str>,
+	pub poster_image: Option<Cow<'a, str>>,
 	/// List of executable profiles.
-	#[serde(rename = "profile")]
+	#[serde(rename = "profile", borrow, skip_serializing_if = "Vec::is_empty")]
 	pub profiles: Vec<MF_Profile_VG_1_0<'a>>,
 	/// List of files to patch.
-	#[serde(rename = "file")]
+	#[serde(rename = "file", borrow, skip_serializing_if = "Vec::is_empty")]
 	pub files: Vec<MF_File_VG_1_0<'a>>,
 }
 
@@ -60,17 +62,19 @@ pub struct Manifest_VG_1_0<'a> {
 #[allow(non_camel_case_types)]
 pub struct MF_Profile_VG_1_0<'a> {
 	/// Profile name.
-	pub name: &'a str,
+	#[serde(borrow)]
+	pub name: Cow<'a, str>,
 	/// Executable file.
-	pub exec: &'a str,
+	#[serde(borrow)]
+	pub exec: Cow<'a, str>,
 	/// Sort order of the profile for UI.
 	pub order: Option<u8>,
 	/// Application params for launch.
-	pub params: Option<&'a str>,
+	pub params: Option<Cow<'a, str>>,
 	/// Application icon
-	pub icon: Option<&'a str>,
+	pub icon: Option<Cow<'a, str>>,
 	/// Application architecture. Assumed to be x64 if missing.
-	pub architecture: Option<&'a str>,
+	pub architecture: Option<Cow<'a, str>>,
 }
 
 /// Defines a patchable file. MD5, SHA1, or SHA256 is required for secure patching.
@@ -78,25 +82,30 @@ pub struct MF_Profile_VG_1_0<'a> {
 #[allow(non_camel_case_types)]
 pub struct MF_File_VG_1_0<'a> {
 	/// Filepath of the file on disk, relative to app dir.
-	pub path: &'a str,
+	#[serde(borrow)]
+	pub path: Cow<'a, str>,
 	/// URL(s) to retrieve the file from.
-	pub url: Vec<&'a str>,
+	pub url: Vec<Cow<'a, str>>,
 	/// Size in bytes of the file.
 	pub size: Option<u64>,
 	/// MD5 hash of the file.
-	pub md5: Option<&'a str>,
+	pub md5: Option<Cow<'a, str>>,
 	/// SHA1 hash of the file.
-	pub sha1: Option<&'a str>,
+	pub sha1: Option<Cow<'a, str>>,
 	/// SHA256 hash of the file.
-	pub sha256: Option<&'a str>,
+	pub sha256: Option<Cow<'a, str>>,
 }
 
 /// Implementation of VG Manifest 1.0 -> Manifest conversion
 impl From<Manifest_VG_1_0<'_>> for Manifest {
 	fn from(item: Manifest_VG_1_0) -> Self {
 		Self {
-			version: item.version.to_owned(),
-			label: item.label.to_owned(),
+			version: item.version.into_owned(),
+			label: item.label.into_owned(),
+			app_version: None,
+			build: None,
+			release_date: None,
+			changelog: None,
 			profiles: item.profiles.into_iter().map(|e| e.into()).collect(),
 			files: item.files.into_iter().map(|e| e.into()).collect(),
 			webpage: item.webpage.map(String::from),
@@ -110,8 +119,8 @@ impl From<Manifest_VG_1_0<'_>> for Manifest {
 impl From<MF_Profile_VG_1_0<'_>> for ManifestProfile {
 	fn from(item: MF_Profile_VG_1_0) -> Self {
 		Self {
-			name: item.name.to_owned(),
-			exec: item.exec.to_owned(),
+			name: item.name.into_owned(),
+			exec: item.exec.into_owned(),
 			order: item.order,
 			params: item.params.map(String::from),
 			icon: item.icon.map(String::from),
@@ -122,7 +131,7 @@ impl From<MF_Profile_VG_1_0<'_>> for ManifestProfile {
 impl From<MF_File_VG_1_0<'_>> for ManifestFile {
 	fn from(item: MF_File_VG_1_0) -> Self {
 		Self {
-			path: item.path.to_owned(),
+			path: item.path.into_owned(),
 			url: item.url.into_iter().map(String::from).collect(),
 			size: item.size,
 			md5: item.md5.map(String::from),
@@ -136,37 +145,37 @@ impl From<MF_File_VG_1_0<'_>> for ManifestFile {
 impl<'a> From<&'a Manifest> for Manifest_VG_1_0<'a> {
 	fn from(item: &'a Manifest) -> Self {
 		Self {
-			version: item.version.as_str(),
-			label: item.label.as_str(),
+			version: item.version.as_str().into(),
+			label: item.label.as_str().into(),
 			profiles: item
 				.profiles
 				.iter()
 				.map(|e| MF_Profile_VG_1_0::<'a> {
-					name: e.name.as_str(),
-					exec: e.exec.as_str(),
+					name: e.name.as_str().into(),
+					exec: e.exec.as_str().into(),
 					order: e.order,
-					params: e.params.as_deref(),
-					icon: e.icon.as_deref(),
-					architecture: e.architecture.as_deref(),
+					params: e.params.as_deref().map(Cow::Borrowed),
+					icon: e.icon.as_deref().map(Cow::Borrowed),
+					architecture: e.architecture.as_deref().map(Cow::Borrowed),
 				})
 				.collect(),
 			files: item
 				.files
 				.iter()
 				.map(|e| MF_File_VG_1_0::<'a> {
-					path: e.path.as_str(),
-					url: e.url.iter().map(|u| u.as_str()).collect(),
+					path: e.path.as_str().into(),
+					url: e.url.iter().map(|u| u.as_str().into()).collect(),
 					size: e.size,
-					md5: e.md5.as_deref(),
-					sha1: e.sha1.as_deref(),
-					sha256: e.sha256.as_deref(),
+					md5: e.md5.as_deref().map(Cow::Borrowed),
+					sha1: e.sha1.as_deref().map(Cow::Borrowed),
+					sha256: e.sha256.as_deref().map(Cow::Borrowed),
 				})
 				.collect(),
-			webpage: item.webpage.as_deref(),
-			forums: item.forums.as_deref(),
-			poster_image: item.poster_image.as_deref(),
-			discord: item.discord.as_deref(),
-			rss: item.rss.as_deref(),
+			webpage: item.webpage.as_deref().map(Cow::Borrowed),
+			forums: item.forums.as_deref().map(Cow::Borrowed),
+			poster_image: item.poster_image.as_deref().map(Cow::Borrowed),
+			discord: item.discord.as_deref().map(Cow::Borrowed),
+			rss: item.rss.as_deref().map(Cow::Borrowed),
 		}
 	}
 }
@@ -199,6 +208,10 @@ mod tests {
 		let test_manifest = Manifest {
 			version: "vg-1.0".to_owned(),
 			label: "Test Manifest".to_owned(),
+			app_version: Some("1.5.0".to_owned()),
+			build: Some(1042),
+			release_date: None,
+			changelog: None,
 			profiles: vec![ManifestProfile {
 				name: "Awesome App".to_owned(),
 				exec: "app.exe".to_owned(),
@@ -314,7 +327,7 @@ mod tests {
 			path = "app2.exe"
 			url = ["https://example.download.mirror/app2.exe"]
 		"#;
-		let deser = deserialize_manifest(&test_toml).unwrap();
+		let deser = deserialize_manifest(test_toml).unwrap();
 	
 		assert_eq!(deser.label, "Test Manifest");
 	
@@ -344,4 +357,17 @@ mod tests {
 		assert_eq!(deser.rss.unwrap(), "https://example.com/some-rss-feed.rss");
 		assert_eq!(deser.poster_image.unwrap(), "https://example.com/some-image.png");
 	}
+
+	#[test]
+	fn should_require_files() {
+		let test_toml = r#"
+			version = "vg-1.0"
+			label = "Test Manifest"
+
+			[[profile]]
+			name = "Awesome App"
+			exec = "app.exe"
+		"#;
+		assert!(deserialize_manifest(test_toml).is_err());
+	}
 }
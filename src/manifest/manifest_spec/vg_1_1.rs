@@ -0,0 +1,157 @@
+// --- Imports
+use super::super::ManifestError;
+use super::vg_1_0::{MF_File_VG_1_0, MF_Profile_VG_1_0};
+use super::Manifest;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Version identifier
+const VG_1_1_VERSION: &str = "vg-1.1";
+
+/// Serializes the contents of a `Manifest` into a Vanguard 1.1 (`vg-1.1`) TOML format.
+/// # Arguments
+/// * `manifest` - The Manifest object.
+pub fn serialize_manifest(manifest: &Manifest) -> Result<String, ManifestError> {
+	// Cast to the versioned struct and overwrite the version property.
+	let mut versioned_manifest: Manifest_VG_1_1 = manifest.into();
+	versioned_manifest.version = Cow::Borrowed(VG_1_1_VERSION);
+	// Serialize
+	let serialized = toml::to_string(&versioned_manifest)?;
+	Ok(serialized)
+}
+
+/// Deserializes the contents of a Vanguard 1.1 (`vg-1.1`) manifest file, returning a `Manifest`.
+/// # Arguments
+/// * `manifest` - A string slice containing the manifest file contents
+pub fn deserialize_manifest(manifest: &str) -> Result<Manifest, ManifestError> {
+	let versioned_manifest: Manifest_VG_1_1 = toml::from_str(manifest)?;
+	Ok(versioned_manifest.into())
+}
+
+/// Manifest version `vg-1.1` (Vanguard TOML 1.1)
+/// Extends `vg-1.0` with application release metadata. Profile and file tables are unchanged.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename = "manifest")]
+#[allow(non_camel_case_types)]
+pub struct Manifest_VG_1_1<'a> {
+	/// Version identifier (ie, vg-1.1).
+	#[serde(borrow)]
+	pub version: Cow<'a, str>,
+	/// Global application name for the manifest.
+	#[serde(borrow)]
+	pub label: Cow<'a, str>,
+	/// Application version described by the manifest (ie, 1.5.0).
+	pub app_version: Option<Cow<'a, str>>,
+	/// Monotonically increasing build number of the application.
+	pub build: Option<u64>,
+	/// Release date of the application version, as an ISO 8601 date (ie, 2020-03-14).
+	pub release_date: Option<Cow<'a, str>>,
+	/// Release notes for the application version.
+	pub changelog: Option<Cow<'a, str>>,
+	/// URL of the webpage for the application.
+	pub webpage: Option<Cow<'a, str>>,
+	/// URL of the forums for the application.
+	pub forums: Option<Cow<'a, str>>,
+	/// Discord invite link for the application's Discord community.
+	pub discord: Option<Cow<'a, str>>,
+	/// URL for an RSS news feed for the application.
+	pub rss: Option<Cow<'a, str>>,
+	/// URL of a banner image to display on GUI launchers.
+	pub poster_image: Option<Cow<'a, str>>,
+	/// List of executable profiles.
+	#[serde(rename = "profile", borrow, default, skip_serializing_if = "Vec::is_empty")]
+	pub profiles: Vec<MF_Profile_VG_1_0<'a>>,
+	/// List of files to patch.
+	#[serde(rename = "file", borrow, default, skip_serializing_if = "Vec::is_empty")]
+	pub files: Vec<MF_File_VG_1_0<'a>>,
+}
+
+/// Implementation of VG Manifest 1.1 -> Manifest conversion
+impl From<Manifest_VG_1_1<'_>> for Manifest {
+	fn from(item: Manifest_VG_1_1) -> Self {
+		Self {
+			version: item.version.into_owned(),
+			label: item.label.into_owned(),
+			app_version: item.app_version.map(String::from),
+			build: item.build,
+			release_date: item.release_date.map(String::from),
+			changelog: item.changelog.map(String::from),
+			profiles: item.profiles.into_iter().map(|e| e.into()).collect(),
+			files: item.files.into_iter().map(|e| e.into()).collect(),
+			webpage: item.webpage.map(String::from),
+			forums: item.forums.map(String::from),
+			poster_image: item.poster_image.map(String::from),
+			discord: item.discord.map(String::from),
+			rss: item.rss.map(String::from),
+		}
+	}
+}
+
+/// Implementation of &Manifest -> VG Manifest 1.1 conversion
+impl<'a> From<&'a Manifest> for Manifest_VG_1_1<'a> {
+	fn from(item: &'a Manifest) -> Self {
+		// Profiles and files share the vg-1.0 representation.
+		let base: super::vg_1_0::Manifest_VG_1_0<'a> = item.into();
+		Self {
+			version: base.version,
+			label: base.label,
+			app_version: item.app_version.as_deref().map(Cow::Borrowed),
+			build: item.build,
+			release_date: item.release_date.as_deref().map(Cow::Borrowed),
+			changelog: item.changelog.as_deref().map(Cow::Borrowed),
+			profiles: base.profiles,
+			files: base.files,
+			webpage: base.webpage,
+			forums: base.forums,
+			poster_image: base.poster_image,
+			discord: base.discord,
+			rss: base.rss,
+		}
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_round_trip() {
+		let test_toml = r#"
+			version = "vg-1.1"
+			label = "Test Manifest"
+			app_version = "1.5.0"
+			build = 1042
+			release_date = "2020-03-14"
+			changelog = """
+			* Made the app more awesome
+			* Fixed a crash on launch"""
+
+			[[profile]]
+			name = "Awesome App"
+			exec = "app.exe"
+
+			[[file]]
+			path = "app.exe"
+			url = ["https://example.download.mirror/app.exe"]
+			sha256 = "the-realest-hash"
+		"#;
+		let deser = deserialize_manifest(test_toml).unwrap();
+
+		assert_eq!(deser.version, "vg-1.1");
+		assert_eq!(deser.app_version.as_ref().unwrap(), "1.5.0");
+		assert_eq!(deser.build.unwrap(), 1042);
+		assert_eq!(deser.release_date.as_ref().unwrap(), "2020-03-14");
+		assert!(deser.changelog.as_ref().unwrap().contains("Fixed a crash on launch"));
+		assert_eq!(deser.profiles[0].exec, "app.exe");
+		assert_eq!(deser.files[0].sha256.as_ref().unwrap(), "the-realest-hash");
+
+		let reser = deserialize_manifest(&serialize_manifest(&deser).unwrap()).unwrap();
+		assert_eq!(reser.app_version, deser.app_version);
+		assert_eq!(reser.build, deser.build);
+		assert_eq!(reser.release_date, deser.release_date);
+		assert_eq!(reser.changelog, deser.changelog);
+	}
+}
@@ -1,7 +1,12 @@
 // --- Modules
+pub mod cache;
+pub mod channel;
+pub mod diff;
+pub mod fetch;
 pub mod manifest_spec;
 
 // --- Imports
+use crate::net::NetError;
 use manifest_spec::Manifest;
 use serde::Deserialize;
 
@@ -16,31 +21,57 @@ struct ManifestVersion<'a> {
 pub fn deserialize_manifest(manifest: &str) -> Result<Manifest, ManifestError> {
 	match manifest.find("<?xml") {
 		// TOML file types
-		None => match toml::from_str::<ManifestVersion>(&manifest)?.version {
-			"vg-1.0" => manifest_spec::vg_1_0::deserialize_manifest(&manifest),
+		None => match toml::from_str::<ManifestVersion>(manifest)?.version {
+			"vg-1.0" => manifest_spec::vg_1_0::deserialize_manifest(manifest),
+			"vg-1.1" => manifest_spec::vg_1_1::deserialize_manifest(manifest),
 			_ => Err(ManifestError::UnknownType)
 		},
 		// XML file types
-		Some(_) => manifest_spec::tq_xml::deserialize_manifest(&manifest)
+		Some(_) => manifest_spec::tq_xml::deserialize_manifest(manifest)
 	}
 }
 
+/// Checks that applying manifest `target` over manifest `installed` does not downgrade the application.
+/// Manifests without release metadata can not be ordered, and are always allowed.
+/// # Arguments
+/// * `installed` - The currently installed Manifest.
+/// * `target` - The Manifest to be applied.
+/// * `allow_downgrade` - If true, downgrades are permitted.
+pub fn check_downgrade(installed: &Manifest, target: &Manifest, allow_downgrade: bool) -> Result<(), ManifestError> {
+	if !allow_downgrade && target.is_downgrade_from(installed) {
+		let describe = |m: &Manifest| match (m.app_version.as_deref(), m.build) {
+			(Some(version), Some(build)) => format!("{} (build {})", version, build),
+			(Some(version), None) => version.to_owned(),
+			(None, Some(build)) => format!("build {}", build),
+			(None, None) => String::new(),
+		};
+		return Err(ManifestError::Downgrade(describe(installed), describe(target)));
+	}
+	Ok(())
+}
+
 /// Defines a Manifest IO / parse error
 #[derive(Debug)]
 pub enum ManifestError {
+	Downgrade(String, String),
 	InvalidModel(toml::ser::Error),
 	InvalidSyntax(toml::de::Error),
 	InvalidXML(roxmltree::Error),
 	MissingRequiredValue(&'static str),
+	Net(NetError),
+	UnknownChannel(String),
 	UnknownType,
 }
 impl std::fmt::Display for ManifestError {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match *self {
+			ManifestError::Downgrade(ref from, ref to) => write!(f, "Refusing to downgrade from {} to {}", from, to),
 			ManifestError::InvalidModel(ref e) => e.fmt(f),
 			ManifestError::InvalidSyntax(ref e) => e.fmt(f),
 			ManifestError::InvalidXML(ref e) => e.fmt(f),
 			ManifestError::MissingRequiredValue(ref desc) => write!(f, "Missing required value: {}", desc),
+			ManifestError::Net(ref e) => e.fmt(f),
+			ManifestError::UnknownChannel(ref name) => write!(f, "Unknown release channel: {}", name),
 			ManifestError::UnknownType => write!(f, "Could not determine manifest format/version."),
 		}
 	}
@@ -48,11 +79,124 @@ impl std::fmt::Display for ManifestError {
 impl std::error::Error for ManifestError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
+			ManifestError::Downgrade(_, _) => None,
 			ManifestError::InvalidModel(ref e) => Some(e),
 			ManifestError::InvalidSyntax(ref e) => Some(e),
 			ManifestError::InvalidXML(ref e) => Some(e),
-			ManifestError::MissingRequiredValue(ref _desc) => None,
+			ManifestError::MissingRequiredValue(_) => None,
+			ManifestError::Net(ref e) => Some(e),
+			ManifestError::UnknownChannel(_) => None,
 			ManifestError::UnknownType => None
 		}
 	}
-}
\ No newline at end of file
+}
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::manifest_spec::{vg_1_0, vg_1_1, ManifestFile, ManifestProfile};
+	use super::*;
+	use proptest::prelude::*;
+
+	fn arb_string() -> impl Strategy<Value = String> {
+		any::<String>()
+	}
+
+	fn arb_profile() -> impl Strategy<Value = ManifestProfile> {
+		(
+			arb_string(),
+			arb_string(),
+			any::<Option<u8>>(),
+			proptest::option::of(arb_string()),
+			proptest::option::of(arb_string()),
+			proptest::option::of(arb_string()),
+		)
+			.prop_map(|(name, exec, order, params, icon, architecture)| ManifestProfile {
+				name,
+				exec,
+				order,
+				params,
+				icon,
+				architecture,
+			})
+	}
+
+	fn arb_file() -> impl Strategy<Value = ManifestFile> {
+		(
+			arb_string(),
+			proptest::collection::vec(arb_string(), 0..4),
+			// TOML integers are signed 64-bit
+			proptest::option::of(0..=i64::MAX as u64),
+			proptest::option::of(arb_string()),
+			proptest::option::of(arb_string()),
+			proptest::option::of(arb_string()),
+		)
+			.prop_map(|(path, url, size, md5, sha1, sha256)| ManifestFile { path, url, size, md5, sha1, sha256 })
+	}
+
+	prop_compose! {
+		fn arb_manifest()(
+			label in arb_string(),
+			app_version in proptest::option::of(arb_string()),
+			build in proptest::option::of(0..=i64::MAX as u64),
+			release_date in proptest::option::of(arb_string()),
+			changelog in proptest::option::of(arb_string()),
+			links in proptest::collection::vec(proptest::option::of(arb_string()), 5),
+			profiles in proptest::collection::vec(arb_profile(), 0..4),
+			files in proptest::collection::vec(arb_file(), 0..8),
+		) -> Manifest {
+			Manifest {
+				version: "vg-1.1".to_owned(),
+				label,
+				app_version,
+				build,
+				release_date,
+				changelog,
+				webpage: links[0].clone(),
+				forums: links[1].clone(),
+				discord: links[2].clone(),
+				rss: links[3].clone(),
+				poster_image: links[4].clone(),
+				profiles,
+				files,
+			}
+		}
+	}
+
+	proptest! {
+		#[test]
+		fn should_round_trip_vg_1_1(manifest in arb_manifest()) {
+			let ser = vg_1_1::serialize_manifest(&manifest).unwrap();
+			prop_assert_eq!(deserialize_manifest(&ser).unwrap(), manifest);
+		}
+
+		#[test]
+		fn should_round_trip_vg_1_0(mut manifest in arb_manifest()) {
+			// vg-1.0 requires at least one profile and file, and does not support release metadata.
+			prop_assume!(!manifest.profiles.is_empty() && !manifest.files.is_empty());
+			manifest.version = "vg-1.0".to_owned();
+			manifest.app_version = None;
+			manifest.build = None;
+			manifest.release_date = None;
+			manifest.changelog = None;
+			let ser = vg_1_0::serialize_manifest(&manifest).unwrap();
+			prop_assert_eq!(deserialize_manifest(&ser).unwrap(), manifest);
+		}
+
+		#[test]
+		fn should_not_panic_on_arbitrary_input(input in any::<String>()) {
+			let _ = deserialize_manifest(&input);
+		}
+
+		#[test]
+		fn should_not_panic_on_arbitrary_toml(input in "version = \"vg-1\\.[01]\"\n[a-z_\\[\\]=\"0-9\n. ]{0,256}") {
+			let _ = deserialize_manifest(&input);
+		}
+
+		#[test]
+		fn should_not_panic_on_arbitrary_xml(input in "<\\?xml version=\"1\\.0\"\\?><manifest>[<>/a-z =\"\\\\]{0,256}") {
+			let _ = deserialize_manifest(&input);
+		}
+	}
+}
@@ -0,0 +1,188 @@
+// --- Modules
+pub mod client;
+pub mod retry;
+
+// --- Imports
+use client::Client;
+use std::error;
+use std::fmt;
+use std::time::Duration;
+use url::Url;
+
+// --- Consts
+/// Maximum number of redirects followed for a single request.
+pub const MAX_REDIRECTS: u32 = 5;
+
+/// Returns true if `url` is fetched over a secure transport.
+pub fn is_secure(url: &str) -> bool {
+	match Url::parse(url) {
+		Ok(parsed) => parsed.scheme() == "https",
+		Err(_) => false,
+	}
+}
+
+/// Returns true if `url` is a `file://` URL, read from the local filesystem rather than fetched.
+pub fn is_local(url: &str) -> bool {
+	match Url::parse(url) {
+		Ok(parsed) => parsed.scheme() == "file",
+		Err(_) => false,
+	}
+}
+
+/// Checks `url` against the secure patching policy.
+/// Local `file://` URLs involve no transport, so are always permitted.
+/// # Arguments
+/// * `url` - The URL to be fetched.
+/// * `allow_insecure` - If true, plain http URLs are permitted.
+pub fn check_url(url: &str, allow_insecure: bool) -> Result<(), NetError> {
+	match allow_insecure || is_secure(url) || is_local(url) {
+		true => Ok(()),
+		false => Err(NetError::InsecureUrl(url.to_owned())),
+	}
+}
+
+/// Checks a redirect from `from` to `to` against the secure patching policy.
+/// Secure requests may never be downgraded by a redirect unless insecure patching is allowed, as a redirect to http
+/// would silently discard the transport security the manifest asked for.
+pub fn check_redirect(from: &str, to: &str, allow_insecure: bool) -> Result<(), NetError> {
+	match allow_insecure || is_secure(to) {
+		true => Ok(()),
+		false => Err(NetError::InsecureRedirect(from.to_owned(), to.to_owned())),
+	}
+}
+
+/// Sends a GET request for `url`, enforcing the secure patching policy on the URL and every redirect.
+/// Returns the final successful response.
+/// # Arguments
+/// * `client` - Client used to send requests.
+/// * `url` - The URL to fetch.
+/// * `allow_insecure` - If true, plain http URLs and redirects are permitted.
+/// * `timeout_ms` - Connect and read timeouts, in milliseconds.
+pub fn get(client: &Client, url: &str, allow_insecure: bool, timeout_ms: (u64, u64)) -> Result<ureq::Response, NetError> {
+	get_with_headers(client, url, allow_insecure, timeout_ms, &[])
+}
+
+/// Sends a GET request for `url` with additional request headers, enforcing the secure patching policy.
+/// Headers are sent with every redirected request.
+/// # Arguments
+/// * `client` - Client used to send requests.
+/// * `url` - The URL to fetch.
+/// * `allow_insecure` - If true, plain http URLs and redirects are permitted.
+/// * `timeout_ms` - Connect and read timeouts, in milliseconds.
+/// * `headers` - Header names and values to send.
+pub fn get_with_headers(
+	client: &Client, url: &str, allow_insecure: bool, timeout_ms: (u64, u64), headers: &[(&str, &str)],
+) -> Result<ureq::Response, NetError> {
+	check_url(url, allow_insecure)?;
+	let mut current = url.to_owned();
+	for _ in 0..=MAX_REDIRECTS {
+		// Redirects are followed here rather than by ureq, so each hop can be checked.
+		let mut request = client.request("GET", &current);
+		for (name, value) in headers {
+			request.set(name, value);
+		}
+		let response = request.timeout_connect(timeout_ms.0).timeout_read(timeout_ms.1).redirects(0).call();
+		if let Some(ref e) = *response.synthetic_error() {
+			return Err(NetError::Network(current, e.body_text()));
+		}
+		// 304 Not Modified answers a conditional request, so is returned to the caller rather than followed.
+		if !response.redirect() || response.status() == 304 {
+			return match response.ok() {
+				true => Ok(response),
+				false => {
+					let retry_after = response.header("retry-after").and_then(retry::parse_retry_after);
+					Err(NetError::Http(current, response.status(), retry_after))
+				}
+			};
+		}
+		let location = response.header("location").ok_or_else(|| NetError::Http(current.clone(), response.status(), None))?;
+		let next = Url::parse(&current)
+			.and_then(|base| base.join(location))
+			.map_err(|e| NetError::Network(current.clone(), format!("Bad redirect to {}: {}", location, e)))?
+			.to_string();
+		check_redirect(&current, &next, allow_insecure)?;
+		current = next;
+	}
+	Err(NetError::TooManyRedirects(url.to_owned()))
+}
+
+/// Defines a network request error.
+#[derive(Debug)]
+pub enum NetError {
+	/// A server returned an unsuccessful HTTP status. Contains the URL, status code, and the delay the server asked
+	/// for before retrying with `Retry-After`, if any.
+	Http(String, u16, Option<Duration>),
+	/// A secure request was redirected to an insecure URL. Contains the redirecting and target URLs.
+	InsecureRedirect(String, String),
+	/// An insecure URL was requested while secure patching is enforced.
+	InsecureUrl(String),
+	/// A connection failed. Contains the URL and error description.
+	Network(String, String),
+	/// A request was redirected more than `MAX_REDIRECTS` times. Contains the original URL.
+	TooManyRedirects(String),
+}
+impl fmt::Display for NetError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			NetError::Http(ref url, status, _) => write!(f, "HTTP {} from {}", status, url),
+			NetError::InsecureRedirect(ref from, ref to) => {
+				write!(f, "Refusing insecure redirect from {} to {}", from, to)
+			}
+			NetError::InsecureUrl(ref url) => write!(f, "Refusing insecure URL while secure patching is enabled: {}", url),
+			NetError::Network(ref url, ref desc) => write!(f, "Network error for {} - {}", url, desc),
+			NetError::TooManyRedirects(ref url) => write!(f, "Too many redirects for {}", url),
+		}
+	}
+}
+impl error::Error for NetError {}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::test_server::{TestResponse, TestServer};
+
+	#[test]
+	fn should_enforce_secure_urls() {
+		assert!(check_url("https://cdn.example.com/app.exe", false).is_ok());
+		assert!(check_url("http://cdn.example.com/app.exe", true).is_ok());
+		assert!(check_url("file:///mnt/usb/app.exe", false).is_ok());
+		for insecure in &["http://cdn.example.com/app.exe", "ftp://cdn.example.com/app.exe", "not a url"] {
+			match check_url(insecure, false) {
+				Err(NetError::InsecureUrl(_)) => (),
+				other => panic!("Unexpected result for {}: {:?}", insecure, other),
+			}
+		}
+		assert!(check_redirect("https://a.example.com/", "https://b.example.com/", false).is_ok());
+		assert!(check_redirect("https://a.example.com/", "http://b.example.com/", true).is_ok());
+		match check_redirect("https://a.example.com/", "http://b.example.com/", false) {
+			Err(NetError::InsecureRedirect(_, ref to)) => assert_eq!(to, "http://b.example.com/"),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn should_follow_redirects() {
+		let server = TestServer::start(|request| match request.path.as_str() {
+			"/moved" => TestResponse::status(302).header("Location", "/target"),
+			"/loop" => TestResponse::status(302).header("Location", "/loop"),
+			_ => TestResponse::ok(b"abc"),
+		});
+		let client = Client::default();
+
+		let response = get(&client, &server.url("/moved"), true, (1000, 1000)).unwrap();
+		assert_eq!(response.into_string().unwrap(), "abc");
+		assert_eq!(server.hits("/target"), 1);
+
+		match get(&client, &server.url("/loop"), true, (1000, 1000)) {
+			Err(NetError::TooManyRedirects(_)) => (),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+		match get(&client, &server.url("/moved"), false, (1000, 1000)) {
+			Err(NetError::InsecureUrl(_)) => assert_eq!(server.hits("/moved"), 1),
+			other => panic!("Unexpected result: {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,168 @@
+// --- Imports
+use super::NetError;
+use crate::config::RetryPolicy;
+use crate::events::{EventSink, PatchEvent};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Returns true if `error` is retried under `policy`.
+pub fn is_retryable(policy: &RetryPolicy, error: &NetError) -> bool {
+	match *error {
+		NetError::Http(_, status, _) => policy.retry_statuses.contains(&status),
+		NetError::Network(_, _) => policy.retry_network_errors,
+		_ => false,
+	}
+}
+
+/// Gets the backoff delay before retry number `retry`, counting from 1, before any jitter is applied.
+pub fn backoff(policy: &RetryPolicy, retry: u32) -> Duration {
+	let delay = policy.backoff_base_ms.saturating_mul(1u64.checked_shl(retry.saturating_sub(1)).unwrap_or(u64::MAX));
+	Duration::from_millis(delay.min(policy.backoff_cap_ms))
+}
+
+/// Gets the delay before retrying a request whose attempt number `attempt`, counting from 1, failed with `error`.
+/// Returns None if the request should not be retried.
+/// Delays requested by the server with `Retry-After` are honoured up to the policy's `max_retry_after_ms`, and are
+/// not jittered.
+pub fn retry_delay(policy: &RetryPolicy, attempt: u32, error: &NetError) -> Option<Duration> {
+	if attempt >= policy.max_attempts || !is_retryable(policy, error) {
+		return None;
+	}
+	if let NetError::Http(_, _, Some(after)) = *error {
+		return Some(after.min(Duration::from_millis(policy.max_retry_after_ms)));
+	}
+	let delay = backoff(policy, attempt);
+	match policy.jitter {
+		true => Some(delay / 2 + Duration::from_millis(random_below(delay.as_millis() as u64 / 2 + 1))),
+		false => Some(delay),
+	}
+}
+
+/// Calls `request` until it succeeds, fails with an error which is not retried, or runs out of attempts.
+/// Each retry is logged and reported to `events` before waiting.
+/// # Arguments
+/// * `policy` - The retry policy.
+/// * `url` - URL of the request, for reporting.
+/// * `events` - Sink which is told of each retry.
+/// * `request` - Sends the request.
+pub fn with_retries<T, F>(policy: &RetryPolicy, url: &str, events: &EventSink, mut request: F) -> Result<T, NetError>
+where
+	F: FnMut() -> Result<T, NetError>,
+{
+	let mut attempt = 1;
+	loop {
+		match request() {
+			Err(e) => match retry_delay(policy, attempt, &e) {
+				Some(delay) => {
+					report_retry(policy, events, url, attempt, delay, &e);
+					thread::sleep(delay);
+					attempt += 1;
+				}
+				None => return Err(e),
+			},
+			result => return result,
+		}
+	}
+}
+
+/// Logs a retry and reports it to `events`.
+/// # Arguments
+/// * `policy` - The retry policy.
+/// * `events` - Sink which is told of the retry.
+/// * `url` - URL of the request.
+/// * `attempt` - Number of the attempt which failed, counting from 1.
+/// * `delay` - Delay before the retry.
+/// * `error` - The error which caused the retry.
+pub fn report_retry(policy: &RetryPolicy, events: &EventSink, url: &str, attempt: u32, delay: Duration, error: &NetError) {
+	log::warn!("{} - retrying in {} ms (attempt {} of {})", error, delay.as_millis(), attempt + 1, policy.max_attempts);
+	events.emit(PatchEvent::Retrying(url.to_owned(), attempt, delay, error.to_string()));
+}
+
+/// Parses the value of a `Retry-After` header, which is either a number of seconds or an HTTP date.
+/// Dates in the past yield a zero delay.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+	let value = value.trim();
+	if let Ok(seconds) = value.parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+	let at = UNIX_EPOCH + Duration::from_secs(parse_http_date(value)?);
+	Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Parses an IMF-fixdate (ie, `Sun, 06 Nov 1994 08:49:37 GMT`) as seconds since the Unix epoch.
+fn parse_http_date(value: &str) -> Option<u64> {
+	const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+	let parts: Vec<&str> = value.split_whitespace().collect();
+	if parts.len() != 6 || parts[5] != "GMT" {
+		return None;
+	}
+	let day: u64 = parts[1].parse().ok()?;
+	let month = MONTHS.iter().position(|m| *m == parts[2])? as u64 + 1;
+	let year: u64 = parts[3].parse().ok()?;
+	let time: Vec<u64> = parts[4].split(':').map(|t| t.parse().ok()).collect::<Option<_>>()?;
+	if year < 1970 || !(1..=31).contains(&day) || time.len() != 3 || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+		return None;
+	}
+	// Days since the epoch of a proleptic Gregorian date, counting years from March so leap days fall last.
+	let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+	let era_days = 365 * y + y / 4 - y / 100 + y / 400 + (153 * m + 2) / 5 + day - 1;
+	let days = era_days.checked_sub(719_468)?;
+	Some(days * 86_400 + time[0] * 3_600 + time[1] * 60 + time[2])
+}
+
+/// Gets a random number below `bound`, for jittering delays.
+fn random_below(bound: u64) -> u64 {
+	let random = RandomState::new().build_hasher().finish();
+	match bound {
+		0 => 0,
+		_ => random % bound,
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn should_back_off_exponentially() {
+		let policy = RetryPolicy {
+			backoff_base_ms: 100,
+			backoff_cap_ms: 1_000,
+			max_retry_after_ms: 10_000,
+			jitter: false,
+			..Default::default()
+		};
+		let delays: Vec<u128> = (1..=6).map(|retry| backoff(&policy, retry).as_millis()).collect();
+		assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+		assert_eq!(backoff(&policy, 200), Duration::from_millis(1_000));
+
+		let unavailable = NetError::Http("https://cdn/".to_owned(), 503, None);
+		assert_eq!(retry_delay(&policy, 1, &unavailable), Some(Duration::from_millis(100)));
+		assert_eq!(retry_delay(&policy, 3, &unavailable), None);
+		assert_eq!(retry_delay(&policy, 1, &NetError::Http("https://cdn/".to_owned(), 404, None)), None);
+		let throttled = NetError::Http("https://cdn/".to_owned(), 429, Some(Duration::from_secs(5)));
+		assert_eq!(retry_delay(&policy, 1, &throttled), Some(Duration::from_secs(5)));
+		let throttled = NetError::Http("https://cdn/".to_owned(), 429, Some(Duration::from_secs(60)));
+		assert_eq!(retry_delay(&policy, 1, &throttled), Some(Duration::from_secs(10)));
+
+		let jittered = RetryPolicy { jitter: true, ..policy };
+		for _ in 0..20 {
+			let delay = retry_delay(&jittered, 2, &unavailable).unwrap();
+			assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200), "{:?}", delay);
+		}
+	}
+
+	#[test]
+	fn should_parse_retry_after() {
+		assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+		assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+		assert_eq!(parse_http_date("Tue, 29 Feb 2000 00:00:00 GMT"), Some(951_782_400));
+		assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::from_secs(0)));
+		assert_eq!(parse_retry_after("soon"), None);
+	}
+}
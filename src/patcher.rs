@@ -0,0 +1,292 @@
+// --- Imports
+use crate::config::{Config, ManifestConfig};
+use crate::file_manager::download::DownloadResult;
+use crate::file_manager::gc::{self, GcOptions, GcReport};
+use crate::file_manager::mirror::{self, MirrorStats, MirrorTracker};
+use crate::file_manager::plan::{self, UpdatePlan};
+use crate::file_manager::FileManagerError;
+use crate::manifest::cache::ManifestCache;
+use crate::manifest::fetch::{self, FetchedManifest};
+use crate::net::client::Client;
+use crate::session::PatchSession;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Defines the outcome of updating a single manifest.
+#[derive(Debug)]
+pub struct ManifestUpdate {
+	/// Configured URL of the manifest.
+	pub url: String,
+	pub result: Result<UpdateOutcome, FileManagerError>,
+}
+
+/// Defines an update which was planned and applied.
+#[derive(Debug)]
+pub struct UpdateOutcome {
+	/// The plan which was applied.
+	pub plan: UpdatePlan,
+	/// One result per file installed, in the order of the plan. Empty if the manifest was already up to date.
+	pub results: Vec<DownloadResult>,
+}
+
+impl UpdateOutcome {
+	/// Returns true if every file of the plan was installed.
+	pub fn is_complete(&self) -> bool {
+		self.results.iter().all(|download| download.result.is_ok())
+	}
+}
+
+/// Updates the manifests configured in `Config.manifests`.
+/// Manifests are updated together, downloading the files of every manifest on one pool of up to
+/// `maximum_parallel_files` workers, and a single mirror tracker ranks the mirrors of every manifest. With symlinked
+/// storage, every manifest installs from the same content store, so files shared between manifests are only
+/// downloaded once.
+#[derive(Debug)]
+pub struct Patcher {
+	config: Config,
+	cache: ManifestCache,
+	mirrors: Arc<MirrorTracker>,
+	session: PatchSession,
+	/// Path mirror statistics are saved to after each update, if any.
+	stats_path: Option<PathBuf>,
+}
+
+impl Patcher {
+	/// Creates a patcher for `config`.
+	/// # Arguments
+	/// * `config` - Application config.
+	/// * `cache` - Cache of previously fetched manifests.
+	/// * `mirrors` - Mirror tracker shared by every manifest.
+	/// * `session` - Session every update belongs to. Cancelling it stops the update of every manifest.
+	pub fn new(config: Config, cache: ManifestCache, mirrors: Arc<MirrorTracker>, session: PatchSession) -> Patcher {
+		Patcher { config, cache, mirrors, session, stats_path: None }
+	}
+
+	/// Creates a patcher for `config` which keeps its manifest cache and mirror statistics next to `vanguard.toml`.
+	pub fn from_app_dir(config: Config, session: PatchSession) -> Result<Patcher, FileManagerError> {
+		let stats_path = mirror::get_stats_path()?;
+		let mirrors = Arc::new(MirrorTracker::with_stats(MirrorStats::load(&stats_path)));
+		let patcher = Patcher::new(config, ManifestCache::from_app_dir()?, mirrors, session);
+		Ok(Patcher { stats_path: Some(stats_path), ..patcher })
+	}
+
+	/// Iterates over the configured manifests. Entries without a manifest URL are ignored.
+	pub fn manifests(&self) -> impl Iterator<Item = &ManifestConfig> {
+		self.config.manifests.iter().filter(|manifest_config| !manifest_config.url.is_empty())
+	}
+
+	/// Plans the update of every configured manifest without downloading any files. Returns one result per manifest,
+	/// in config order, with the configured URL of the manifest.
+	pub fn plan_all(&self) -> Vec<(String, Result<UpdatePlan, FileManagerError>)> {
+		self.manifests()
+			.map(|manifest_config| {
+				let planned = plan::plan_update(&self.config, manifest_config, &self.cache, self.session.events());
+				(manifest_config.url.clone(), planned)
+			})
+			.collect()
+	}
+
+	/// Updates every configured manifest, returning one result per manifest in config order.
+	/// Every manifest is planned first, then the files of every plan are downloaded on one shared pool of workers.
+	/// A manifest which fails to update does not stop the others from updating.
+	pub fn update_all(&self) -> Vec<ManifestUpdate> {
+		let manifest_configs: Vec<&ManifestConfig> = self.manifests().collect();
+		let plans: Vec<Result<UpdatePlan, FileManagerError>> =
+			manifest_configs.iter().map(|manifest_config| self.plan_manifest(manifest_config, None)).collect();
+		let planned: Vec<(&ManifestConfig, &UpdatePlan)> = manifest_configs
+			.iter()
+			.zip(&plans)
+			.filter_map(|(&manifest_config, plan)| plan.as_ref().ok().map(|plan| (manifest_config, plan)))
+			.collect();
+		let mut applied = plan::apply_plans(&self.config, &planned, &self.mirrors, &self.session).into_iter();
+		let updates = manifest_configs
+			.iter()
+			.zip(plans)
+			.map(|(manifest_config, plan)| {
+				let result = plan.and_then(|plan| {
+					let results = applied.next().unwrap_or_else(|| Ok(Vec::new()))?;
+					Ok(UpdateOutcome { plan, results })
+				});
+				ManifestUpdate { url: manifest_config.url.clone(), result }
+			})
+			.collect();
+		self.save_stats();
+		updates
+	}
+
+	/// Updates the configured manifest with URL or label `key`.
+	/// URLs are matched against the config first. Labels are only known once a manifest is fetched, so otherwise
+	/// manifests are fetched in config order until one has a matching label.
+	pub fn update(&self, key: &str) -> Result<ManifestUpdate, FileManagerError> {
+		if let Some(manifest_config) = self.manifests().find(|manifest_config| manifest_config.url == key) {
+			let update = self.update_manifest(manifest_config, None);
+			self.save_stats();
+			return Ok(update);
+		}
+		for manifest_config in self.manifests() {
+			match self.fetch(manifest_config) {
+				Ok(fetched) if fetched.manifest.label == key => {
+					let update = self.update_manifest(manifest_config, Some(fetched));
+					self.save_stats();
+					return Ok(update);
+				}
+				Ok(_) => (),
+				Err(e) => log::warn!("Could not fetch {} while looking for {} - {}", manifest_config.url, key, e),
+			}
+		}
+		Err(FileManagerError::UnknownManifest(key.to_owned()))
+	}
+
+	/// Plans and applies the update of a single manifest, fetching it unless it has already been fetched.
+	/// Manifests older than the installed release fail with `ManifestError::Downgrade`, unless the manifest config
+	/// allows downgrades. Plans with no changes are still applied, so the installed release is recorded.
+	fn update_manifest(&self, manifest_config: &ManifestConfig, fetched: Option<FetchedManifest>) -> ManifestUpdate {
+		let result = self.plan_manifest(manifest_config, fetched).and_then(|plan| {
+			let results = plan::apply_plan(&self.config, manifest_config, &plan, &self.mirrors, &self.session)?;
+			Ok(UpdateOutcome { plan, results })
+		});
+		ManifestUpdate { url: manifest_config.url.clone(), result }
+	}
+
+	/// Plans the update of a single manifest, fetching it unless it has already been fetched.
+	fn plan_manifest(
+		&self, manifest_config: &ManifestConfig, fetched: Option<FetchedManifest>,
+	) -> Result<UpdatePlan, FileManagerError> {
+		if self.session.is_cancelled() {
+			return Err(FileManagerError::Cancelled);
+		}
+		let fetched = fetched.map_or_else(|| self.fetch(manifest_config), Ok)?;
+		plan::plan_manifest(&self.config, manifest_config, &fetched.manifest, fetched.source)
+	}
+
+	/// Removes blobs which no installed manifest uses from the content store. See `gc::collect_garbage`.
+	pub fn collect_garbage(&self, options: GcOptions) -> Result<GcReport, FileManagerError> {
+		gc::collect_garbage(&self.config, options)
+	}
+
+	fn fetch(&self, manifest_config: &ManifestConfig) -> Result<FetchedManifest, FileManagerError> {
+		let client = Client::for_manifest(&self.config, manifest_config)?;
+		Ok(fetch::fetch_manifest(&client, &self.config, manifest_config, &self.cache, self.session.events())?)
+	}
+
+	/// Saves mirror statistics, if the patcher keeps them. Statistics are only an optimisation, so failures are
+	/// logged rather than returned.
+	fn save_stats(&self) {
+		if let Some(ref stats_path) = self.stats_path {
+			if let Err(e) = self.mirrors.stats().save(stats_path) {
+				log::warn!("Could not save mirror statistics - {}", e);
+			}
+		}
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::manifest::manifest_spec::{vg_1_1, Manifest, ManifestFile};
+	use crate::manifest::ManifestError;
+	use crate::test_fixtures::{self, manifest_config};
+	use crate::test_server::{TestResponse, TestServer};
+	use std::fs;
+	use std::sync::Mutex;
+
+	fn manifest(label: &str, files: Vec<ManifestFile>) -> Manifest {
+		Manifest { label: label.to_owned(), ..test_fixtures::manifest(files) }
+	}
+
+	#[test]
+	fn should_update_configured_manifests() {
+		let documents: Arc<Mutex<Vec<(String, String)>>> = Arc::new(Mutex::new(Vec::new()));
+		let served = documents.clone();
+		let server = TestServer::start(move |request| {
+			match served.lock().unwrap().iter().find(|(path, _)| *path == request.path) {
+				Some((_, document)) => TestResponse::ok(document.as_bytes()),
+				None if request.path == "/abc" => TestResponse::ok(b"abc"),
+				None => TestResponse::status(404),
+			}
+		});
+		let file = |path: &str| test_fixtures::file(path, &server.url("/abc"), b"abc");
+		for (path, label, file_path) in &[("/a.toml", "App A", "a.exe"), ("/b.toml", "App B", "b.exe")] {
+			let document = vg_1_1::serialize_manifest(&manifest(label, vec![file(file_path)])).unwrap();
+			documents.lock().unwrap().push((path.to_string(), document));
+		}
+
+		let dir = tempfile::tempdir().unwrap();
+		let configured = |path: &str, app: &str| manifest_config(&server.url(path), &dir.path().join(app));
+		let mut config = Config {
+			use_symlinked_storage: true,
+			storage_path: Some(dir.path().join("store").to_string_lossy().into_owned()),
+			..Default::default()
+		};
+		config.retry.max_attempts = 1;
+		config.manifests = vec![
+			configured("/a.toml", "a"),
+			configured("/missing.toml", "missing"),
+			configured("/b.toml", "b"),
+		];
+		let cache = ManifestCache::open(&dir.path().join("cache"));
+		let patcher = Patcher::new(config, cache, Arc::new(MirrorTracker::new()), PatchSession::default());
+
+		let updates = patcher.update_all();
+		assert_eq!(updates.iter().map(|u| u.url.clone()).collect::<Vec<_>>(), vec![
+			server.url("/a.toml"),
+			server.url("/missing.toml"),
+			server.url("/b.toml")
+		]);
+		assert!(updates[0].result.as_ref().unwrap().is_complete());
+		assert!(updates[1].result.is_err());
+		assert!(updates[2].result.as_ref().unwrap().is_complete());
+		assert_eq!(fs::read(dir.path().join("a/a.exe")).unwrap(), b"abc");
+		assert_eq!(fs::read(dir.path().join("b/b.exe")).unwrap(), b"abc");
+		// The file shared by both manifests is downloaded once, and installed from the content store for the second.
+		assert_eq!(server.hits("/abc"), 1);
+
+		let document = vg_1_1::serialize_manifest(&manifest("App B", vec![file("b.exe"), file("b2.exe")])).unwrap();
+		documents.lock().unwrap()[1].1 = document;
+		let update = patcher.update("App B").unwrap();
+		assert_eq!(update.url, server.url("/b.toml"));
+		let outcome = update.result.unwrap();
+		assert_eq!(outcome.plan.fetch.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["b2.exe"]);
+		assert!(outcome.is_complete());
+		assert!(dir.path().join("b/b2.exe").exists());
+
+		let update = patcher.update(&server.url("/a.toml")).unwrap();
+		assert!(update.result.unwrap().plan.is_empty());
+		match patcher.update("App C") {
+			Err(FileManagerError::UnknownManifest(ref key)) => assert_eq!(key, "App C"),
+			other => panic!("Unexpected result: {:?}", other.map(|u| u.url)),
+		}
+	}
+
+	#[test]
+	fn should_refuse_downgrades() {
+		let document: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+		let served = document.clone();
+		let server = TestServer::start(move |request| match request.path.as_str() {
+			"/abc" => TestResponse::ok(b"abc"),
+			_ => TestResponse::ok(served.lock().unwrap().as_bytes()),
+		});
+		let release = |build| {
+			let file = test_fixtures::file("app.exe", &server.url("/abc"), b"abc");
+			vg_1_1::serialize_manifest(&Manifest { build: Some(build), ..test_fixtures::manifest(vec![file]) }).unwrap()
+		};
+
+		let dir = tempfile::tempdir().unwrap();
+		let mut config = Config { use_symlinked_storage: false, ..Default::default() };
+		config.manifests = vec![manifest_config(&server.url("/Manifest.toml"), dir.path())];
+		let cache = ManifestCache::open(&dir.path().join("cache"));
+		let patcher = Patcher::new(config, cache, Arc::new(MirrorTracker::new()), PatchSession::default());
+
+		*document.lock().unwrap() = release(2);
+		assert!(patcher.update_all()[0].result.as_ref().unwrap().is_complete());
+		*document.lock().unwrap() = release(1);
+		match patcher.update_all()[0].result {
+			Err(FileManagerError::Manifest(ManifestError::Downgrade(_, _))) => (),
+			ref other => panic!("Unexpected result: {:?}", other),
+		}
+		assert_eq!(fs::read(dir.path().join("app.exe")).unwrap(), b"abc");
+	}
+}
@@ -0,0 +1,149 @@
+// --- Imports
+use crate::events::{EventSink, PatchEvent};
+use serde::Serialize;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Defines the state of a patch session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum SessionState {
+	#[default]
+	Running,
+	/// Workers stop at their next safe point and wait to be resumed.
+	Paused,
+	/// Workers stop at their next safe point, and no further work is started. Cancelled sessions can not be resumed.
+	Cancelled,
+}
+
+#[derive(Debug, Default)]
+struct Control {
+	state: Mutex<SessionState>,
+	changed: Condvar,
+}
+
+/// Handle to a patch in progress, used to pause, resume or cancel it and to receive its events.
+/// Handles are cheap to clone, and clones control the same session, so a launcher may keep one while another thread
+/// patches with it.
+/// Downloads stop at safe points between reads, saving their progress so they can be resumed, either when the
+/// session is resumed or by a later session. Files are only swapped into the application path once every download
+/// has finished, so a cancelled patch leaves the application path untouched.
+#[derive(Debug, Clone, Default)]
+pub struct PatchSession {
+	events: EventSink,
+	control: Arc<Control>,
+}
+
+impl PatchSession {
+	/// Creates a running session which reports its events to `events`.
+	pub fn new(events: EventSink) -> PatchSession {
+		PatchSession { events, control: Default::default() }
+	}
+
+	/// Gets the sink which receives the session's events.
+	pub fn events(&self) -> &EventSink {
+		&self.events
+	}
+
+	/// Delivers `event` to the session's event sink.
+	pub fn emit(&self, event: PatchEvent) {
+		self.events.emit(event);
+	}
+
+	/// Gets the current state of the session.
+	pub fn state(&self) -> SessionState {
+		*self.control.state.lock().unwrap()
+	}
+
+	/// Returns true if the session has been cancelled.
+	pub fn is_cancelled(&self) -> bool {
+		self.state() == SessionState::Cancelled
+	}
+
+	/// Pauses a running session.
+	pub fn pause(&self) {
+		self.transition(SessionState::Running, SessionState::Paused);
+	}
+
+	/// Resumes a paused session.
+	pub fn resume(&self) {
+		self.transition(SessionState::Paused, SessionState::Running);
+	}
+
+	/// Cancels the session. Paused workers are woken so they can stop.
+	pub fn cancel(&self) {
+		let mut state = self.control.state.lock().unwrap();
+		if *state != SessionState::Cancelled {
+			*state = SessionState::Cancelled;
+			self.control.changed.notify_all();
+			drop(state);
+			self.emit(PatchEvent::SessionChanged(SessionState::Cancelled));
+		}
+	}
+
+	/// Blocks while the session is paused. Returns the state the session left the pause in, which is either running
+	/// or cancelled.
+	pub fn wait_while_paused(&self) -> SessionState {
+		let state = self.control.state.lock().unwrap();
+		let state = self.control.changed.wait_while(state, |state| *state == SessionState::Paused).unwrap();
+		*state
+	}
+
+	/// Waits for `duration`, returning early if the session is cancelled. Returns the state of the session.
+	pub fn sleep(&self, duration: Duration) -> SessionState {
+		let state = self.control.state.lock().unwrap();
+		let condition = |state: &mut SessionState| *state != SessionState::Cancelled;
+		let (state, _) = self.control.changed.wait_timeout_while(state, duration, condition).unwrap();
+		*state
+	}
+
+	/// Moves the session from `from` to `to`, if it is in `from`.
+	fn transition(&self, from: SessionState, to: SessionState) {
+		let mut state = self.control.state.lock().unwrap();
+		if *state == from {
+			*state = to;
+			self.control.changed.notify_all();
+			drop(state);
+			self.emit(PatchEvent::SessionChanged(to));
+		}
+	}
+}
+
+// --- Tests
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn should_pause_and_resume_sessions() {
+		let (events, received) = EventSink::channel();
+		let session = PatchSession::new(events);
+		session.pause();
+		let waiting = {
+			let session = session.clone();
+			thread::spawn(move || session.wait_while_paused())
+		};
+		thread::sleep(Duration::from_millis(50));
+		assert!(!waiting.is_finished());
+		session.resume();
+		assert_eq!(waiting.join().unwrap(), SessionState::Running);
+
+		session.cancel();
+		// Cancelled sessions stay cancelled.
+		session.resume();
+		session.pause();
+		assert_eq!(session.wait_while_paused(), SessionState::Cancelled);
+		let received: Vec<PatchEvent> = received.try_iter().collect();
+		assert_eq!(
+			received,
+			vec![
+				PatchEvent::SessionChanged(SessionState::Paused),
+				PatchEvent::SessionChanged(SessionState::Running),
+				PatchEvent::SessionChanged(SessionState::Cancelled),
+			]
+		);
+	}
+}
@@ -0,0 +1,44 @@
+//! Manifests and manifest configs shared by tests.
+// Helpers are shared between test modules, which do not all use every one of them.
+#![allow(dead_code)]
+
+// --- Imports
+use crate::config::ManifestConfig;
+use crate::file_manager::hash::{HashAlgorithm, StreamHasher};
+use crate::manifest::manifest_spec::{Manifest, ManifestFile};
+use std::path::Path;
+
+/// Creates the config of the manifest at `url`, installed to `application_path`.
+/// Insecure patching is allowed, as test servers only serve http.
+pub fn manifest_config(url: &str, application_path: &Path) -> ManifestConfig {
+	ManifestConfig {
+		url: url.to_owned(),
+		channel: None,
+		allow_insecure_patching: true,
+		application_path: application_path.to_string_lossy().into_owned(),
+		ignore_checksum: false,
+		allow_downgrade: false,
+		ignore_profiles: Vec::new(),
+		max_download_rate: None,
+		pinned_certificates: Vec::new(),
+	}
+}
+
+/// Creates a vg-1.1 manifest labelled `App`, holding `files`.
+pub fn manifest(files: Vec<ManifestFile>) -> Manifest {
+	Manifest { version: "vg-1.1".to_owned(), label: "App".to_owned(), files, ..Default::default() }
+}
+
+/// Creates a manifest file at `path`, downloaded from `url`, with the size and SHA-1 hash of `contents`.
+pub fn file(path: &str, url: &str, contents: &[u8]) -> ManifestFile {
+	let mut hasher = StreamHasher::new(HashAlgorithm::Sha1);
+	hasher.update(contents);
+	ManifestFile {
+		path: path.to_owned(),
+		url: vec![url.to_owned()],
+		size: Some(contents.len() as u64),
+		md5: None,
+		sha1: Some(hasher.finish().value().to_owned()),
+		sha256: None,
+	}
+}
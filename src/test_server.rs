@@ -0,0 +1,121 @@
+//! Minimal HTTP/1.1 server used to exercise download code paths in tests.
+// Helpers are shared between test modules, which do not all use every one of them.
+#![allow(dead_code)]
+
+// --- Imports
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Request received by the test server.
+#[derive(Debug, Clone)]
+pub struct TestRequest {
+	pub path: String,
+	/// Headers, keyed by lowercase name.
+	pub headers: HashMap<String, String>,
+}
+
+/// Response returned by a test server handler.
+pub struct TestResponse {
+	pub status: u16,
+	pub headers: Vec<(String, String)>,
+	pub body: Vec<u8>,
+}
+impl TestResponse {
+	pub fn ok(body: &[u8]) -> TestResponse {
+		TestResponse { status: 200, headers: Vec::new(), body: body.to_vec() }
+	}
+	pub fn status(status: u16) -> TestResponse {
+		TestResponse { status, headers: Vec::new(), body: Vec::new() }
+	}
+	pub fn with_status(mut self, status: u16) -> TestResponse {
+		self.status = status;
+		self
+	}
+	pub fn header(mut self, name: &str, value: &str) -> TestResponse {
+		self.headers.push((name.to_owned(), value.to_owned()));
+		self
+	}
+}
+
+type Handler = dyn Fn(&TestRequest) -> TestResponse + Send + Sync;
+
+/// HTTP server bound to an ephemeral localhost port. The server runs until the test process exits.
+pub struct TestServer {
+	port: u16,
+	requests: Arc<Mutex<Vec<TestRequest>>>,
+}
+impl TestServer {
+	/// Starts a server which answers every request with `handler`.
+	pub fn start<F>(handler: F) -> TestServer
+	where
+		F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let requests = Arc::new(Mutex::new(Vec::new()));
+		let handler: Arc<Handler> = Arc::new(handler);
+		let thread_requests = requests.clone();
+		thread::spawn(move || {
+			for stream in listener.incoming().flatten() {
+				let handler = handler.clone();
+				let requests = thread_requests.clone();
+				thread::spawn(move || handle_connection(stream, &*handler, &requests));
+			}
+		});
+		TestServer { port, requests }
+	}
+
+	/// Gets the URL of `path` on this server.
+	pub fn url(&self, path: &str) -> String {
+		format!("http://127.0.0.1:{}{}", self.port, path)
+	}
+
+	/// Gets every request received so far.
+	pub fn requests(&self) -> Vec<TestRequest> {
+		self.requests.lock().unwrap().clone()
+	}
+
+	/// Gets the number of requests received for `path`.
+	pub fn hits(&self, path: &str) -> usize {
+		self.requests.lock().unwrap().iter().filter(|r| r.path == path).count()
+	}
+}
+
+fn handle_connection(stream: TcpStream, handler: &Handler, requests: &Mutex<Vec<TestRequest>>) {
+	let mut reader = BufReader::new(stream.try_clone().unwrap());
+	let mut request_line = String::new();
+	if reader.read_line(&mut request_line).is_err() {
+		return;
+	}
+	let path = request_line.split_whitespace().nth(1).unwrap_or("/").to_owned();
+	let mut headers = HashMap::new();
+	loop {
+		let mut line = String::new();
+		if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+			break;
+		}
+		if let Some(index) = line.find(':') {
+			headers.insert(line[..index].trim().to_ascii_lowercase(), line[index + 1..].trim().to_owned());
+		}
+	}
+	let request = TestRequest { path, headers };
+	requests.lock().unwrap().push(request.clone());
+	let response = handler(&request);
+
+	let mut stream = stream;
+	let mut head = format!("HTTP/1.1 {} Test\r\nConnection: close\r\n", response.status);
+	// Handlers may set their own Content-Length to simulate truncated responses.
+	if !response.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-length")) {
+		head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+	}
+	for (name, value) in &response.headers {
+		head.push_str(&format!("{}: {}\r\n", name, value));
+	}
+	head.push_str("\r\n");
+	let _ = stream.write_all(head.as_bytes());
+	let _ = stream.write_all(&response.body);
+	let _ = stream.flush();
+}